@@ -0,0 +1,280 @@
+// Generates a rectangle of chunks without opening a window and writes it out
+// as a 16-bit heightmap, a normal map and optionally an OBJ mesh, so seeds can
+// be previewed and generation output checked.
+//
+//     cargo run --bin worldgen -- --seed 42 --from -4,-4 --to 3,3 --out worldgen --obj
+//
+// Any flag the game takes that applies to noise terrain works here too, like
+// `--terrain cpu` to generate on the CPU or `--erosion <iterations>`.
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use cgmath::Vector3;
+use image::{ImageBuffer, Luma, Rgb};
+
+use rust_game::lib::options::{Options, TerrainSource};
+use rust_game::world::generator::{CpuNoiseGenerator, GpuNoiseGenerator, TerrainGenerator};
+use rust_game::world::noise::{Biome, TerrainVertex};
+use rust_game::world::ChunkCoord;
+
+const USAGE: &str =
+    "usage: worldgen [--from <x>,<z>] [--to <x>,<z>] [--out <dir>] [--obj] [game options...]";
+
+/// Heights the heightmap spans, lowest to highest. Fixed rather than taken
+/// from the terrain so pixels mean the same height in every run and seed.
+const HEIGHTMAP_RANGE: (f32, f32) = (-128.0, 128.0);
+
+struct Args {
+    options: Options,
+    /// Inclusive corners of the rectangle, in chunks.
+    from: ChunkCoord,
+    to: ChunkCoord,
+    out: PathBuf,
+    obj: bool,
+}
+
+fn parse_coord(value: &str) -> Result<ChunkCoord> {
+    let (x, z) = value
+        .split_once(',')
+        .ok_or_else(|| anyhow!("expected <x>,<z>, got {:?}", value))?;
+    Ok(ChunkCoord::new(x.trim().parse()?, z.trim().parse()?))
+}
+
+/// The flags of this tool, with everything else handed to
+/// `Options::from_args`.
+fn parse_args() -> Result<Args> {
+    let mut from = ChunkCoord::new(-4, -4);
+    let mut to = ChunkCoord::new(3, 3);
+    let mut out = PathBuf::from("worldgen");
+    let mut obj = false;
+    let mut game_args = Vec::new();
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        let mut value = || argv.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+        match arg.as_str() {
+            "--from" => from = parse_coord(&value()?)?,
+            "--to" => to = parse_coord(&value()?)?,
+            "--out" => out = value()?.into(),
+            "--obj" => obj = true,
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                std::process::exit(0);
+            }
+            _ => game_args.push(arg),
+        }
+    }
+
+    let mut options = Options::from_args(game_args.into_iter()).context(USAGE)?;
+    // only the most detailed level is written out, and nothing is saved
+    options.lod_count = 1;
+    options.save_dir = None;
+
+    if to.x < from.x || to.z < from.z {
+        bail!("--to {:?} is before --from {:?}", to, from);
+    }
+    Ok(Args {
+        options,
+        from,
+        to,
+        out,
+        obj,
+    })
+}
+
+/// Grid vertices of every chunk in the rectangle stitched into one grid,
+/// row by row along x.
+struct Heightfield {
+    width: usize,
+    depth: usize,
+    vertices: Vec<TerrainVertex>,
+}
+
+impl Heightfield {
+    fn new(args: &Args, chunks: &[(ChunkCoord, Vec<TerrainVertex>)]) -> Self {
+        let resolution = args.options.chunk_dimensions.resolution.cast::<usize>().unwrap();
+        let width = (args.to.x - args.from.x + 1) as usize * resolution.x + 1;
+        let depth = (args.to.z - args.from.z + 1) as usize * resolution.y + 1;
+        let mut vertices = vec![
+            TerrainVertex {
+                position: Vector3::new(0.0, 0.0, 0.0),
+                normal: Vector3::new(0.0, 1.0, 0.0),
+                biome: Biome::Plains,
+            };
+            width * depth
+        ];
+
+        for (coord, chunk) in chunks {
+            let origin_x = (coord.x - args.from.x) as usize * resolution.x;
+            let origin_z = (coord.z - args.from.z) as usize * resolution.y;
+            for (i, vertex) in chunk.iter().enumerate() {
+                // neighbouring chunks generate identical vertices on shared edges
+                let x = origin_x + i % (resolution.x + 1);
+                let z = origin_z + i / (resolution.x + 1);
+                vertices[z * width + x] = *vertex;
+            }
+        }
+
+        Self {
+            width,
+            depth,
+            vertices,
+        }
+    }
+
+    fn height_range(&self) -> (f32, f32) {
+        self.vertices
+            .iter()
+            .map(|v| v.position.y)
+            .fold((f32::MAX, f32::MIN), |(min, max), y| (min.min(y), max.max(y)))
+    }
+
+    /// Heights mapped linearly from `HEIGHTMAP_RANGE` onto the 16-bit range,
+    /// clamped at either end.
+    fn write_heightmap(&self, path: &Path) -> Result<()> {
+        let (min, max) = HEIGHTMAP_RANGE;
+        let pixels = self
+            .vertices
+            .iter()
+            .map(|v| ((v.position.y - min) / (max - min) * 65535.0).clamp(0.0, 65535.0).round() as u16)
+            .collect();
+        ImageBuffer::<Luma<u16>, Vec<u16>>::from_raw(self.width as u32, self.depth as u32, pixels)
+            .unwrap()
+            .save(path)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Normal x, z and y (up) packed into red, green and blue.
+    fn write_normal_map(&self, path: &Path) -> Result<()> {
+        let pack = |n: f32| ((n * 0.5 + 0.5) * 255.0).round() as u8;
+        let pixels = self
+            .vertices
+            .iter()
+            .flat_map(|v| [pack(v.normal.x), pack(v.normal.z), pack(v.normal.y)])
+            .collect();
+        ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(self.width as u32, self.depth as u32, pixels)
+            .unwrap()
+            .save(path)
+            .with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    fn write_obj(&self, path: &Path) -> Result<()> {
+        let file = File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        for v in &self.vertices {
+            writeln!(out, "v {} {} {}", v.position.x, v.position.y, v.position.z)?;
+        }
+        for v in &self.vertices {
+            writeln!(out, "vn {} {} {}", v.normal.x, v.normal.y, v.normal.z)?;
+        }
+        // same triangles as the compute shader, OBJ indices start at 1
+        for z in 0..self.depth - 1 {
+            for x in 0..self.width - 1 {
+                let v00 = z * self.width + x + 1;
+                let v10 = v00 + 1;
+                let v01 = v00 + self.width;
+                let v11 = v01 + 1;
+                writeln!(out, "f {0}//{0} {1}//{1} {2}//{2}", v00, v01, v11)?;
+                writeln!(out, "f {0}//{0} {1}//{1} {2}//{2}", v00, v11, v10)?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+async fn request_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::default();
+    let adapter = instance
+        .request_adapter(&wgpu::RequestAdapterOptions::default())
+        .await?;
+    adapter
+        .request_device(
+            &wgpu::DeviceDescriptor {
+                label: None,
+                features: wgpu::Features::empty(),
+                limits: wgpu::Limits::downlevel_defaults(),
+            },
+            None,
+        )
+        .await
+        .ok()
+}
+
+/// Grid vertices of the most detailed level of every chunk, generated as
+/// many at a time as the generator allows.
+fn generate(
+    generator: &mut dyn TerrainGenerator,
+    options: &Options,
+    coords: &[ChunkCoord],
+) -> Vec<(ChunkCoord, Vec<TerrainVertex>)> {
+    let grid_vertices = options.chunk_dimensions.grid_vertices();
+
+    let mut chunks = Vec::new();
+    for batch in coords.chunks(generator.max_batch_size()) {
+        for (coord, raw) in batch.iter().zip(generator.generate(batch)) {
+            let vertices = (0..grid_vertices).map(|i| raw.lods[0].vertex(i)).collect();
+            chunks.push((*coord, vertices));
+        }
+    }
+    chunks
+}
+
+fn main() {
+    env_logger::init();
+    if let Err(e) = run() {
+        eprintln!("worldgen: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+fn run() -> Result<()> {
+    let args = parse_args()?;
+    let coords: Vec<ChunkCoord> = (args.from.z..=args.to.z)
+        .flat_map(|z| (args.from.x..=args.to.x).map(move |x| ChunkCoord::new(x, z)))
+        .collect();
+
+    let mut generator: Box<dyn TerrainGenerator> = match args.options.terrain {
+        TerrainSource::GpuNoise => {
+            let (device, queue) = pollster::block_on(request_device())
+                .ok_or_else(|| anyhow!("no graphics adapter found, pass --terrain cpu to generate on the CPU"))?;
+            println!("generating {} chunks on the GPU", coords.len());
+            Box::new(GpuNoiseGenerator::new(Arc::new(device), Arc::new(queue), &args.options))
+        }
+        TerrainSource::CpuNoise => {
+            println!("generating {} chunks on the CPU", coords.len());
+            Box::new(CpuNoiseGenerator::new(&args.options))
+        }
+        _ => bail!("worldgen only writes out noise terrain, pass --terrain gpu or cpu"),
+    };
+    let chunks = generate(generator.as_mut(), &args.options, &coords);
+    let heightfield = Heightfield::new(&args, &chunks);
+
+    fs::create_dir_all(&args.out)
+        .with_context(|| format!("failed to create {}", args.out.display()))?;
+    heightfield.write_heightmap(&args.out.join("heightmap.png"))?;
+    heightfield.write_normal_map(&args.out.join("normals.png"))?;
+    if args.obj {
+        heightfield.write_obj(&args.out.join("terrain.obj"))?;
+    }
+
+    let (min, max) = heightfield.height_range();
+    println!(
+        "seed {}: {}x{} samples, heights {} to {} (heightmap {} to {}), written to {}",
+        args.options.seed,
+        heightfield.width,
+        heightfield.depth,
+        min,
+        max,
+        HEIGHTMAP_RANGE.0,
+        HEIGHTMAP_RANGE.1,
+        args.out.display(),
+    );
+    Ok(())
+}
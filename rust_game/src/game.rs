@@ -0,0 +1,12 @@
+// The game's modules as a library, shared by the game and tools like
+// `worldgen`.
+
+// `lib` is a module of the game, not a second crate root
+#![allow(special_module_name)]
+
+pub mod lib;
+pub mod light;
+pub mod world;
+
+#[cfg(test)]
+mod testing;
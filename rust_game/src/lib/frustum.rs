@@ -0,0 +1,101 @@
+use cgmath::{InnerSpace, Matrix, Matrix4, Vector3, Vector4};
+
+use super::camera::{Camera, Projection};
+
+/// Axis-aligned box in world space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Vector3<f32>, max: Vector3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    /// The box stretched up or down to reach height `y`.
+    pub fn including_height(&self, y: f32) -> Self {
+        Self {
+            min: Vector3::new(self.min.x, self.min.y.min(y), self.min.z),
+            max: Vector3::new(self.max.x, self.max.y.max(y), self.max.z),
+        }
+    }
+}
+
+/// The six planes bounding what the camera sees, each facing inwards.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    /// `(normal, distance)` with points inside where `normal · p + distance >= 0`.
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    pub fn new(camera: &Camera, projection: &Projection) -> Self {
+        Self::from_view_proj(projection.calc_matrix() * camera.calc_matrix())
+    }
+
+    /// Planes read straight off the rows of a view-projection matrix (Gribb
+    /// and Hartmann), for clip space depth running from 0 to 1 as in wgpu.
+    pub fn from_view_proj(m: Matrix4<f32>) -> Self {
+        let (x, y, z, w) = (m.row(0), m.row(1), m.row(2), m.row(3));
+        let planes = [w + x, w - x, w + y, w - y, z, w - z].map(|plane| {
+            // normalised so distances to the planes are in world units
+            plane / plane.truncate().magnitude()
+        });
+        Self { planes }
+    }
+
+    /// Whether any of the box may be in view. Boxes near a corner of the
+    /// frustum can pass without being in view, never the other way round.
+    pub fn intersects(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner furthest along the plane's normal
+            let corner = Vector3::new(
+                if plane.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Deg;
+
+    fn frustum() -> Frustum {
+        // at the origin looking along +x
+        let camera = Camera::new((0.0, 0.0, 0.0), Deg(0.0), Deg(0.0));
+        Frustum::new(&camera, &Projection::new(800, 600, Deg(60.0), 0.1, 100.0))
+    }
+
+    fn cube(centre: Vector3<f32>) -> Aabb {
+        Aabb::new(centre - Vector3::new(1.0, 1.0, 1.0), centre + Vector3::new(1.0, 1.0, 1.0))
+    }
+
+    #[test]
+    fn keeps_boxes_in_view() {
+        let frustum = frustum();
+        assert!(frustum.intersects(&cube(Vector3::new(10.0, 0.0, 0.0))));
+        assert!(frustum.intersects(&cube(Vector3::new(50.0, 20.0, -30.0))));
+        // the camera inside the box
+        assert!(frustum.intersects(&cube(Vector3::new(0.0, 0.0, 0.0))));
+        // poking in from the side
+        assert!(frustum.intersects(&Aabb::new(
+            Vector3::new(10.0, -1.0, -100.0),
+            Vector3::new(11.0, 1.0, 0.0),
+        )));
+    }
+
+    #[test]
+    fn culls_boxes_out_of_view() {
+        let frustum = frustum();
+        assert!(!frustum.intersects(&cube(Vector3::new(-10.0, 0.0, 0.0))));
+        assert!(!frustum.intersects(&cube(Vector3::new(10.0, 0.0, 30.0))));
+        assert!(!frustum.intersects(&cube(Vector3::new(10.0, -30.0, 0.0))));
+        assert!(!frustum.intersects(&cube(Vector3::new(150.0, 0.0, 0.0))));
+    }
+}
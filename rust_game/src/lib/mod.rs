@@ -1,16 +1,17 @@
 mod camera;
+pub mod frustum;
 mod instance;
 pub mod model;
 mod resources;
 pub mod run;
 pub mod texture;
-mod options;
+pub mod options;
 pub mod pipelines;
 mod utils;
 
 use std::sync::Arc;
 use camera::CameraUniform;
-use cgmath::{prelude::*, Vector2};
+use cgmath::prelude::*;
 use instance::{Instance, InstanceRaw};
 use model::Vertex;
 use std::iter;
@@ -19,13 +20,14 @@ use wgpu::{
     SurfaceConfiguration,
 };
 use winit::{
-    event::{ElementState, KeyboardInput, MouseButton, WindowEvent},
+    event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
     window::Window,
 };
-use pipelines::ray_intersection::RayIntersectPipeline;
+use frustum::Frustum;
 
 use crate::light::Light;
-use crate::world::World;
+use crate::world::{BrushMode, ChunkDrawStats, ChunkUploadStats, FogUniform, Sculptor, World};
+use options::{Options, MAX_RENDER_DISTANCE, MIN_RENDER_DISTANCE};
 use crate::{lib::model::DrawModel, world};
 
 use crate::lib::model::DrawLight;
@@ -46,7 +48,6 @@ struct State {
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    ray_intersection_pipeline: RayIntersectPipeline,
     instances: Vec<Instance>,
     #[allow(dead_code)]
     instance_buffer: wgpu::Buffer,
@@ -58,8 +59,18 @@ struct State {
     mouse_pressed: bool,
     world: world::World,
     world_pipeline: world::WorldPipeline,
+    /// Chunks drawn and culled in the last frame, for profiling.
+    chunk_draw_stats: ChunkDrawStats,
+    /// Chunks uploaded in the last frame and still waiting, for profiling.
+    chunk_upload_stats: ChunkUploadStats,
+    sculptor: Sculptor,
 }
 
+/// Pipeline drawing with `vs_main` and `fs_main` from `shader`.
+///
+/// Anything not drawn with `BlendState::REPLACE` is treated as transparent:
+/// it is depth tested but leaves the depth buffer alone, so it has to be
+/// drawn after everything opaque.
 pub fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
@@ -67,6 +78,7 @@ pub fn create_render_pipeline(
     depth_format: Option<wgpu::TextureFormat>,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: &wgpu::ShaderModule,
+    blend: wgpu::BlendState,
 ) -> wgpu::RenderPipeline {
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some(&format!("{:?}", shader)),
@@ -81,10 +93,7 @@ pub fn create_render_pipeline(
             entry_point: "fs_main",
             targets: &[Some(wgpu::ColorTargetState {
                 format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
+                blend: Some(blend),
                 write_mask: wgpu::ColorWrites::ALL,
             })],
         }),
@@ -99,7 +108,7 @@ pub fn create_render_pipeline(
         },
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
-            depth_write_enabled: true,
+            depth_write_enabled: blend == wgpu::BlendState::REPLACE,
             depth_compare: wgpu::CompareFunction::Less,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
@@ -122,7 +131,7 @@ impl State {
         adapter: wgpu::Adapter,
         device: Arc<wgpu::Device>,
         queue: Arc<wgpu::Queue>,
-        chunk_size: Vector2<u32>,
+        options: &Options,
     ) -> Self {
         let size = window.inner_size();
 
@@ -185,9 +194,15 @@ impl State {
                 label: Some("texture_bind_group_layout"),
             });
 
+        let world = World::new(&device, options);
         let camera = camera::Camera::new((0.0, 5.0, 0.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection =
-            camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 500.0);
+        let projection = camera::Projection::new(
+            config.width,
+            config.height,
+            cgmath::Deg(45.0),
+            0.1,
+            far_plane(&world),
+        );
         let camera_controller = camera::CameraController::new(10.0, 1.0);
 
         let mut camera_uniform = CameraUniform::new();
@@ -279,21 +294,14 @@ impl State {
 
         let render_pipeline = init_render_pipeline(&device, &render_pipeline_layout, &config);
         let debug_material = init_debug_material(&device, &queue, &texture_bind_group_layout);
-        let world = World::new(chunk_size);
         let world_pipeline = world::WorldPipeline::new(
             &device,
             &camera_bind_group_layout,
             &light.bind_group_layout,
             config.format,
             Some(texture::Texture::DEPTH_FORMAT),
-        );
-
-        let ray_intersection_pipeline = RayIntersectPipeline::new(
-            &device, 
-            &camera,
-            &camera_buffer, 
-            &world,
-            chunk_size,
+            world.view_distance(),
+            world.sea_level(),
         );
 
         Self {
@@ -310,7 +318,6 @@ impl State {
             camera_buffer,
             camera_bind_group,
             camera_uniform,
-            ray_intersection_pipeline,
             instances,
             instance_buffer,
             depth_texture,
@@ -321,6 +328,9 @@ impl State {
             mouse_pressed: true,
             world,
             world_pipeline,
+            chunk_draw_stats: ChunkDrawStats::default(),
+            chunk_upload_stats: ChunkUploadStats::default(),
+            sculptor: Sculptor::default(),
         }
     }
 
@@ -340,8 +350,89 @@ impl State {
         }
     }
 
+    /// Changes how many chunks are kept around the camera, moving the far
+    /// plane and fog along with it.
+    fn set_render_distance(&mut self, render_distance: u32) {
+        let render_distance = render_distance.clamp(MIN_RENDER_DISTANCE, MAX_RENDER_DISTANCE);
+        if render_distance == self.world.render_distance() {
+            return;
+        }
+
+        self.world.set_render_distance(render_distance);
+        self.projection.set_zfar(far_plane(&self.world));
+        self.world_pipeline
+            .set_view_distance(&self.queue, self.world.view_distance());
+        log::info!("Render distance: {} chunks", render_distance);
+    }
+
+    /// 1-4 pick raise, lower, flatten or smooth, the brackets shrink and grow
+    /// the brush, Z undoes and Y redoes.
+    fn sculpt_key(&mut self, key: VirtualKeyCode) {
+        let brush = &mut self.sculptor.brush;
+        match key {
+            VirtualKeyCode::Key1 => brush.mode = BrushMode::Raise,
+            VirtualKeyCode::Key2 => brush.mode = BrushMode::Lower,
+            VirtualKeyCode::Key3 => brush.mode = BrushMode::Flatten,
+            VirtualKeyCode::Key4 => brush.mode = BrushMode::Smooth,
+            VirtualKeyCode::LBracket => brush.set_radius(brush.radius - 1.0),
+            VirtualKeyCode::RBracket => brush.set_radius(brush.radius + 1.0),
+            VirtualKeyCode::Z => {
+                if !self.sculptor.undo(&mut self.world, &self.device, &self.queue) {
+                    log::info!("Nothing to undo");
+                }
+                return;
+            }
+            VirtualKeyCode::Y => {
+                if !self.sculptor.redo(&mut self.world, &self.device, &self.queue) {
+                    log::info!("Nothing to redo");
+                }
+                return;
+            }
+            _ => return,
+        }
+        log::info!("Brush: {:?}, radius {}", brush.mode, brush.radius);
+    }
+
     fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(key @ (VirtualKeyCode::Equals | VirtualKeyCode::Minus)),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                let render_distance = self.world.render_distance();
+                match key {
+                    VirtualKeyCode::Equals => self.set_render_distance(render_distance + 1),
+                    _ => self.set_render_distance(render_distance.saturating_sub(1)),
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode:
+                            Some(
+                                key @ (VirtualKeyCode::Key1
+                                | VirtualKeyCode::Key2
+                                | VirtualKeyCode::Key3
+                                | VirtualKeyCode::Key4
+                                | VirtualKeyCode::LBracket
+                                | VirtualKeyCode::RBracket
+                                | VirtualKeyCode::Z
+                                | VirtualKeyCode::Y),
+                            ),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.sculpt_key(*key);
+                true
+            }
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
@@ -363,32 +454,52 @@ impl State {
                 self.mouse_pressed = *state == ElementState::Pressed;
                 true
             }
+            WindowEvent::MouseInput {
+                button: MouseButton::Right,
+                state,
+                ..
+            } => {
+                match state {
+                    ElementState::Pressed => self.sculptor.begin_stroke(),
+                    ElementState::Released => self.sculptor.end_stroke(),
+                }
+                true
+            }
             _ => false,
         }
     }
 
    async fn update(&mut self, dt: std::time::Duration) {
-        let mut x_anchor = (self.camera.position.x as i32 / 32 as i32) * 32 as i32;
-        let mut z_anchor = (self.camera.position.z as i32 / 32 as i32) * 32 as i32;
-
-        if self.camera.position.x < 0.0 {
-            x_anchor = x_anchor - 32;
+        let previous = self.camera.position;
+        self.camera_controller.update_camera(&mut self.camera, dt);
+        // voxel terrain isn't a height grid, the camera flies freely over it
+        let position = self.camera.position;
+        // the ground is only known once the chunk is on the CPU, until then
+        // the camera keeps its height
+        if self.world.is_heightfield() {
+            let chunk_coord = self.world.chunk_at(position.to_vec());
+            self.world.read_back_chunks(&self.device, &self.queue, [chunk_coord]);
         }
-        if self.camera.position.z < 0.0 {
-            z_anchor = z_anchor - 32;
+        if !self.world.can_walk((previous.x, previous.z), (position.x, position.z)) {
+            self.camera.position.x = previous.x;
+            self.camera.position.z = previous.z;
         }
-        
-        let chunk_key = format!("{}_{}", x_anchor, z_anchor);
-        if self.world.raw_chunk_data.len() > 0 {
-            let result = self.ray_intersection_pipeline.ray_intersect(
-                &self.device, 
-                &self.queue, 
-                self.world.raw_chunk_data[&chunk_key].clone(),
-            ).await;
-            self.camera.position.y = self.camera.position.y - result + 3.0;
+        let position = self.camera.position;
+        // walk on land, swim in water too deep to stand in
+        if let Some(ground) = self.world.height_at(position.x, position.z) {
+            let y = self.camera.position.y;
+            self.camera.position.y = self.world.camera_height(ground, y, dt.as_secs_f32());
         }
-
-        self.camera_controller.update_camera(&mut self.camera, dt);
+        self.world_pipeline.animate_water(&self.queue, dt.as_secs_f32());
+        // the brush follows the centre of the screen, the cursor is hidden
+        self.sculptor.sculpt(
+            &mut self.world,
+            &self.device,
+            &self.queue,
+            self.camera.position.to_vec(),
+            self.camera.forward(),
+            dt.as_secs_f32(),
+        );
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
         self.queue.write_buffer(
@@ -410,7 +521,13 @@ impl State {
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        self.world.ingest_chunk_data(&self.device);
+        self.chunk_upload_stats = self.world.ingest_chunk_data(&self.device, &self.queue);
+        log::trace!("Chunk uploads: {:?}", self.chunk_upload_stats);
+        let frustum = Frustum::new(&self.camera, &self.projection);
+        self.chunk_draw_stats =
+            self.world_pipeline
+                .prepare(&self.device, &self.queue, &self.world, &frustum);
+        log::trace!("Chunks: {:?}", self.chunk_draw_stats);
         let output = self.surface.get_current_texture()?;
         let view = output
             .texture
@@ -429,12 +546,7 @@ impl State {
                     view: &view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.1,
-                            g: 0.2,
-                            b: 0.3,
-                            a: 1.0,
-                        }),
+                        load: wgpu::LoadOp::Clear(FogUniform::COLOR),
                         store: true,
                     },
                 })],
@@ -467,6 +579,7 @@ impl State {
             self.world_pipeline.render(
                 &mut render_pass,
                 &self.world,
+                &frustum,
                 &self.camera_bind_group,
                 &self.light.bind_group,
             );
@@ -478,6 +591,12 @@ impl State {
     }
 }
 
+/// Far plane just past the farthest corner of the loaded chunks.
+fn far_plane(world: &World) -> f32 {
+    let extent = world.chunk_dimensions.extent;
+    world.view_distance() + 2.0 * extent.x.max(extent.y)
+}
+
 fn init_render_pipeline(
     device: &wgpu::Device,
     render_pipeline_layout: &wgpu::PipelineLayout,
@@ -496,6 +615,7 @@ fn init_render_pipeline(
             Some(texture::Texture::DEPTH_FORMAT),
             &[model::ModelVertex::desc(), InstanceRaw::desc()],
             &shader,
+            wgpu::BlendState::REPLACE,
         )
     };
 
@@ -1,4 +1,265 @@
-use winit::window::{Window, WindowBuilder};
+use std::{env, path::PathBuf};
 
-pub struct Options{
-}
\ No newline at end of file
+use anyhow::{anyhow, bail, Context};
+
+use crate::world::ChunkDimensions;
+
+pub const MIN_RENDER_DISTANCE: u32 = 1;
+pub const MAX_RENDER_DISTANCE: u32 = 32;
+
+/// Terrain read from an elevation file instead of generated.
+pub struct HeightmapOptions {
+    /// 8 or 16-bit grayscale PNG, or SRTM `.hgt` tile.
+    pub path: PathBuf,
+    /// World units between neighbouring samples.
+    pub horizontal_scale: f32,
+    /// World units per metre of a `.hgt` tile, or for the full range of a PNG.
+    pub vertical_scale: f32,
+}
+
+/// Where the heights of the terrain come from.
+pub enum TerrainSource {
+    /// Noise generated in a compute shader.
+    GpuNoise,
+    /// The same noise generated on the CPU.
+    CpuNoise,
+    /// A level plane at the given height, for testing.
+    Flat(f32),
+    /// An elevation file.
+    Heightmap(HeightmapOptions),
+    /// A 3D density field generated in a compute shader, with caves and
+    /// overhangs.
+    Voxel,
+}
+
+impl TerrainSource {
+    /// Whether chunks are height grids in the layout `gen_terrain_compute`
+    /// writes, which sculpting, water and walking on the ground rely on.
+    /// Voxel chunks are free-form triangle lists.
+    pub fn is_heightfield(&self) -> bool {
+        !matches!(self, Self::Voxel)
+    }
+}
+
+/// Erosion run over generated terrain. Laid out to be uploaded to the
+/// shader as it is.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct ErosionOptions {
+    /// Rounds of water flow and slope collapse.
+    pub iterations: u32,
+    /// Water falling on every grid cell each round.
+    pub rain: f32,
+    /// Share of the water on a cell that dries up each round.
+    pub evaporation: f32,
+    /// Sediment carried per unit of water running out of a cell.
+    pub sediment_capacity: f32,
+    /// Share of the spare carrying capacity dug out of the ground each round.
+    pub erosion_rate: f32,
+    /// Share of the sediment over capacity dropped each round.
+    pub deposition_rate: f32,
+    /// Steepest slope, as height over distance, that doesn't collapse.
+    pub talus: f32,
+    /// Share of the height over the talus slope that slides down each round.
+    pub thermal_rate: f32,
+}
+
+impl ErosionOptions {
+    /// Grid cells generated around each chunk. Every round lets the edge of
+    /// the grid disturb two more cells, and normals read one more.
+    pub fn margin(&self) -> u32 {
+        self.iterations * 2 + 1
+    }
+}
+
+impl Default for ErosionOptions {
+    fn default() -> Self {
+        Self {
+            iterations: 16,
+            rain: 0.02,
+            evaporation: 0.05,
+            sediment_capacity: 2.0,
+            erosion_rate: 0.3,
+            deposition_rate: 0.3,
+            talus: 1.2,
+            thermal_rate: 0.5,
+        }
+    }
+}
+
+pub struct Options {
+    /// Picks the terrain; the same seed always generates the same world.
+    pub seed: u32,
+    pub chunk_dimensions: ChunkDimensions,
+    /// Radius, in chunks, of the area kept loaded around the camera.
+    pub render_distance: u32,
+    /// Number of levels of detail generated for every chunk.
+    pub lod_count: u32,
+    /// Distance, in chunks, covered by each level of detail.
+    pub lod_distance: u32,
+    /// Most chunks the generator keeps cached after they leave render distance.
+    pub chunk_cache_size: usize,
+    /// Most bytes of chunk data the generator keeps cached.
+    pub chunk_cache_bytes: usize,
+    /// Bytes of newly loaded chunks uploaded per frame. The rest wait for
+    /// later frames, though at least one chunk goes up every frame.
+    pub chunk_upload_bytes: usize,
+    /// Directory generated chunks are saved to and loaded from, by default
+    /// `world` in `data_dir`. `None` keeps the world in memory only.
+    pub save_dir: Option<PathBuf>,
+    pub terrain: TerrainSource,
+    /// Erodes noise terrain when set. Not applied to other sources.
+    pub erosion: Option<ErosionOptions>,
+    /// Height of the water surface. Terrain below it is under water.
+    pub sea_level: f32,
+}
+
+impl Options {
+    /// Defaults overridden by command line flags: `--seed <number>`,
+    /// `--save-dir <path>`, `--no-save`,
+    /// `--terrain <gpu|cpu|flat[:<height>]|voxel>`,
+    /// `--heightmap <path>`,
+    /// `--heightmap-scale <horizontal>,<vertical>`, `--erosion <iterations>`
+    /// and `--sea-level <height>`. Erosion is otherwise left at its defaults.
+    pub fn from_args(mut args: impl Iterator<Item = String>) -> anyhow::Result<Self> {
+        let mut options = Self::default();
+        let mut heightmap_scale = None;
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| anyhow!("{} needs a value", arg));
+            match arg.as_str() {
+                "--seed" => {
+                    let seed = value()?;
+                    options.seed = seed
+                        .parse()
+                        .with_context(|| format!("invalid seed {:?}", seed))?;
+                }
+                "--save-dir" => options.save_dir = Some(value()?.into()),
+                "--no-save" => options.save_dir = None,
+                "--terrain" => {
+                    let terrain = value()?;
+                    let (source, height) = match terrain.split_once(':') {
+                        Some((source, height)) => (source, Some(height)),
+                        None => (terrain.as_str(), None),
+                    };
+                    options.terrain = match (source, height) {
+                        ("gpu", None) => TerrainSource::GpuNoise,
+                        ("cpu", None) => TerrainSource::CpuNoise,
+                        ("flat", None) => TerrainSource::Flat(0.0),
+                        ("flat", Some(height)) => TerrainSource::Flat(
+                            height
+                                .parse()
+                                .with_context(|| format!("invalid flat height {:?}", height))?,
+                        ),
+                        ("voxel", None) => TerrainSource::Voxel,
+                        _ => bail!(
+                            "unknown terrain {:?}, expected gpu, cpu, flat[:<height>] or voxel",
+                            terrain
+                        ),
+                    }
+                }
+                "--heightmap" => {
+                    options.terrain = TerrainSource::Heightmap(HeightmapOptions {
+                        path: value()?.into(),
+                        horizontal_scale: 1.0,
+                        vertical_scale: 1.0,
+                    })
+                }
+                "--heightmap-scale" => {
+                    let scale = value()?;
+                    let (horizontal, vertical) = scale
+                        .split_once(',')
+                        .ok_or_else(|| anyhow!("expected <horizontal>,<vertical>, got {:?}", scale))?;
+                    heightmap_scale = Some((
+                        horizontal.trim().parse().context("invalid horizontal scale")?,
+                        vertical.trim().parse().context("invalid vertical scale")?,
+                    ));
+                }
+                "--erosion" => {
+                    let iterations = value()?;
+                    options.erosion = Some(ErosionOptions {
+                        iterations: iterations
+                            .parse()
+                            .with_context(|| format!("invalid erosion iterations {:?}", iterations))?,
+                        ..Default::default()
+                    });
+                }
+                "--sea-level" => {
+                    let sea_level = value()?;
+                    options.sea_level = sea_level
+                        .parse()
+                        .with_context(|| format!("invalid sea level {:?}", sea_level))?;
+                }
+                _ => bail!("unknown argument {:?}", arg),
+            }
+        }
+
+        if let Some((horizontal, vertical)) = heightmap_scale {
+            let TerrainSource::Heightmap(heightmap) = &mut options.terrain else {
+                bail!("--heightmap-scale needs --heightmap");
+            };
+            heightmap.horizontal_scale = horizontal;
+            heightmap.vertical_scale = vertical;
+        }
+        Ok(options)
+    }
+
+    /// Levels of detail actually usable with the configured chunk resolution.
+    pub fn lod_count(&self) -> u32 {
+        self.lod_count.clamp(1, self.chunk_dimensions.max_lod_count())
+    }
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            seed: 0,
+            chunk_dimensions: ChunkDimensions::new((32, 32).into(), (32.0, 32.0).into()),
+            render_distance: 10,
+            lod_count: 3,
+            lod_distance: 4,
+            chunk_cache_size: 1024,
+            chunk_cache_bytes: 256 * 1024 * 1024,
+            chunk_upload_bytes: 1024 * 1024,
+            save_dir: data_dir().map(|dir| dir.join("world")),
+            terrain: TerrainSource::GpuNoise,
+            erosion: None,
+            sea_level: 0.0,
+        }
+    }
+}
+
+/// The game's directory among the user's application data, wherever the
+/// game is started from. `None` where the platform doesn't say where that
+/// is, leaving the world unsaved unless `--save-dir` is given.
+pub fn data_dir() -> Option<PathBuf> {
+    let var = |name| env::var_os(name).map(PathBuf::from).filter(|path| path.is_absolute());
+    let base = if cfg!(windows) {
+        var("APPDATA")
+    } else if cfg!(target_os = "macos") {
+        var("HOME").map(|home| home.join("Library/Application Support"))
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))
+    };
+    base.map(|dir| dir.join("rust_game"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> anyhow::Result<Options> {
+        Options::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_flat_terrain_height() {
+        let flat_height = |terrain| match parse(&["--terrain", terrain]).unwrap().terrain {
+            TerrainSource::Flat(height) => height,
+            _ => panic!("{} isn't flat terrain", terrain),
+        };
+        assert_eq!(flat_height("flat"), 0.0);
+        assert_eq!(flat_height("flat:-12.5"), -12.5);
+        assert!(parse(&["--terrain", "flat:high"]).is_err());
+        assert!(parse(&["--terrain", "gpu:3"]).is_err());
+    }
+}
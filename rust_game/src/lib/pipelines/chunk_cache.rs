@@ -0,0 +1,177 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::world::ChunkCoord;
+
+use super::load_chunks::ChunkMeshes;
+
+/// Counters describing how well the cache is doing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Chunks served from the cache instead of being loaded or generated.
+    pub hits: u64,
+    /// Chunks looked up and not found, so loaded or generated.
+    pub misses: u64,
+    pub evictions: u64,
+    pub chunks: usize,
+    pub bytes: usize,
+}
+
+struct CacheEntry {
+    data: ChunkMeshes,
+    last_used: u64,
+}
+
+/// Generated chunk data kept around after it leaves the requested set, so
+/// walking back over a boundary doesn't regenerate the same chunks. Chunks
+/// left on the GPU count against the byte budget just the same.
+///
+/// Bounded by both a chunk count and a byte budget; whichever is hit first
+/// evicts the least recently used chunks.
+pub struct ChunkCache {
+    entries: HashMap<ChunkCoord, CacheEntry>,
+    // last_used tick -> chunk, oldest first
+    recency: BTreeMap<u64, ChunkCoord>,
+    tick: u64,
+    max_chunks: usize,
+    max_bytes: usize,
+    stats: CacheStats,
+}
+
+impl ChunkCache {
+    pub fn new(max_chunks: usize, max_bytes: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            max_chunks,
+            max_bytes,
+            stats: CacheStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.stats
+    }
+
+    pub fn contains(&self, coord: &ChunkCoord) -> bool {
+        self.entries.contains_key(coord)
+    }
+
+    /// Looks up a chunk the world is missing, marking it as most recently
+    /// used and counting the hit or miss. Only for chunks about to be handed
+    /// to the world or made, so the counts say how often chunks are reused.
+    pub fn get(&mut self, coord: &ChunkCoord) -> Option<&ChunkMeshes> {
+        self.tick += 1;
+        match self.entries.get_mut(coord) {
+            Some(entry) => {
+                self.recency.remove(&entry.last_used);
+                self.recency.insert(self.tick, *coord);
+                entry.last_used = self.tick;
+                self.stats.hits += 1;
+                Some(&entry.data)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    pub fn insert(&mut self, coord: ChunkCoord, data: ChunkMeshes) {
+        self.tick += 1;
+        self.stats.bytes += data.size_bytes();
+        if let Some(old) = self.entries.insert(
+            coord,
+            CacheEntry {
+                data,
+                last_used: self.tick,
+            },
+        ) {
+            self.recency.remove(&old.last_used);
+            self.stats.bytes -= old.data.size_bytes();
+        }
+        self.recency.insert(self.tick, coord);
+        self.stats.chunks = self.entries.len();
+
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        // always keep the chunk that was just inserted
+        while self.entries.len() > 1
+            && (self.entries.len() > self.max_chunks || self.stats.bytes > self.max_bytes)
+        {
+            let Some((_, coord)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&coord) {
+                self.stats.bytes -= entry.data.size_bytes();
+                self.stats.evictions += 1;
+            }
+        }
+        self.stats.chunks = self.entries.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::pipelines::load_chunks::{RawBufferData, RawChunkData};
+
+    fn chunk(bytes: usize) -> ChunkMeshes {
+        ChunkMeshes::Cpu(RawChunkData::new(vec![RawBufferData {
+            vertex_data: Vec::new(),
+            index_data: vec![0; bytes],
+        }]))
+    }
+
+    fn coord(x: i32) -> ChunkCoord {
+        ChunkCoord::new(x, 0)
+    }
+
+    #[test]
+    fn evicts_least_recently_used_past_the_chunk_limit() {
+        let mut cache = ChunkCache::new(3, usize::MAX);
+        for x in 0..3 {
+            cache.insert(coord(x), chunk(4));
+        }
+        // using the oldest makes the second oldest the first to go
+        assert!(cache.get(&coord(0)).is_some());
+        cache.insert(coord(3), chunk(4));
+        cache.insert(coord(4), chunk(4));
+
+        assert!([0, 3, 4].iter().all(|x| cache.contains(&coord(*x))));
+        assert!(!cache.contains(&coord(1)) && !cache.contains(&coord(2)));
+        let stats = cache.stats();
+        assert_eq!((stats.chunks, stats.bytes, stats.evictions), (3, 12, 2));
+    }
+
+    #[test]
+    fn evicts_least_recently_used_past_the_byte_budget() {
+        let mut cache = ChunkCache::new(usize::MAX, 100);
+        cache.insert(coord(0), chunk(40));
+        cache.insert(coord(1), chunk(40));
+        cache.insert(coord(2), chunk(40));
+        assert!(!cache.contains(&coord(0)));
+        assert_eq!(cache.stats().bytes, 80);
+
+        // replacing a chunk counts only its new size
+        cache.insert(coord(1), chunk(10));
+        assert_eq!(cache.stats().bytes, 50);
+
+        // a chunk over the whole budget still stays, alone
+        cache.insert(coord(3), chunk(200));
+        assert!(cache.contains(&coord(3)));
+        assert_eq!((cache.stats().chunks, cache.stats().bytes), (1, 200));
+    }
+
+    #[test]
+    fn counts_lookups() {
+        let mut cache = ChunkCache::new(8, usize::MAX);
+        assert!(cache.get(&coord(0)).is_none());
+        cache.insert(coord(0), chunk(4));
+        assert!(cache.contains(&coord(0)));
+        assert!(cache.get(&coord(0)).is_some());
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 1));
+    }
+}
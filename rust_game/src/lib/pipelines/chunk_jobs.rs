@@ -0,0 +1,185 @@
+use std::{
+    collections::HashSet,
+    sync::{
+        mpsc::{self, Receiver, Sender, TryRecvError},
+        Arc,
+    },
+    thread::{self, JoinHandle},
+};
+
+use crate::lib::options::Options;
+use crate::world::{new_generator, ChunkCoord};
+
+use super::load_chunks::{ChunkMeshes, ComputeWorld, RawChunkData};
+
+/// Messages from the main thread to the generation worker.
+enum ChunkJob {
+    /// Chunks wanted, highest priority first. They move ahead of anything
+    /// already pending.
+    Request(Vec<ChunkCoord>),
+    /// Chunks no longer wanted. Ones not generated yet are dropped.
+    Cancel(Vec<ChunkCoord>),
+    /// A chunk edited in game, saved in place of the generated one.
+    Store(ChunkCoord, RawChunkData),
+    Shutdown,
+}
+
+/// Handle to the thread generating chunks in the background.
+///
+/// Requests go out as messages and finished chunks come back over a second
+/// channel. Anything that finishes after it was cancelled is thrown away
+/// rather than handed to the world.
+pub struct ChunkJobs {
+    jobs: Sender<ChunkJob>,
+    completed: Receiver<(ChunkCoord, ChunkMeshes)>,
+    worker: Option<JoinHandle<()>>,
+    // requested and not yet completed
+    in_flight: HashSet<ChunkCoord>,
+    last_request: Vec<ChunkCoord>,
+}
+
+impl ChunkJobs {
+    pub fn spawn(
+        device: Arc<wgpu::Device>,
+        queue: Arc<wgpu::Queue>,
+        options: &Options,
+    ) -> anyhow::Result<Self> {
+        let (jobs, job_receiver) = mpsc::channel();
+        let (completed_sender, completed) = mpsc::channel();
+
+        let generator = new_generator(options, Arc::clone(&device), Arc::clone(&queue))?;
+        let world = ComputeWorld::new(options, generator, device, queue);
+        let worker = thread::Builder::new()
+            .name("chunk generation".into())
+            .spawn(move || {
+                Worker {
+                    world,
+                    jobs: job_receiver,
+                    completed: completed_sender,
+                    pending: Vec::new(),
+                }
+                .run()
+            })
+            .expect("failed to spawn chunk generation thread");
+
+        Ok(Self {
+            jobs,
+            completed,
+            worker: Some(worker),
+            in_flight: HashSet::new(),
+            last_request: Vec::new(),
+        })
+    }
+
+    /// Replaces the set of wanted chunks with `queue`, in priority order.
+    /// Chunks requested earlier but missing from `queue` are cancelled.
+    pub fn request(&mut self, queue: Vec<ChunkCoord>) {
+        if queue == self.last_request {
+            return;
+        }
+
+        let wanted: HashSet<ChunkCoord> = queue.iter().copied().collect();
+        let cancelled: Vec<ChunkCoord> = self.in_flight.difference(&wanted).copied().collect();
+        if !cancelled.is_empty() {
+            self.send(ChunkJob::Cancel(cancelled));
+        }
+        self.send(ChunkJob::Request(queue.clone()));
+
+        self.in_flight = wanted;
+        self.last_request = queue;
+    }
+
+    /// Chunks finished since the last call that are still wanted.
+    pub fn completed(&mut self) -> impl Iterator<Item = (ChunkCoord, ChunkMeshes)> + '_ {
+        let in_flight = &mut self.in_flight;
+        self.completed
+            .try_iter()
+            .filter(move |(coord, _)| in_flight.remove(coord))
+    }
+
+    /// Hands an edited chunk to the worker to save, so it is loaded with the
+    /// edits the next time it comes into range.
+    pub fn store(&self, coord: ChunkCoord, chunk: RawChunkData) {
+        self.send(ChunkJob::Store(coord, chunk));
+    }
+
+    /// Stops the worker once it finishes the batch it is on and waits for it.
+    pub fn shutdown(&mut self) {
+        if let Some(worker) = self.worker.take() {
+            self.send(ChunkJob::Shutdown);
+            if worker.join().is_err() {
+                log::error!("chunk generation thread panicked");
+            }
+        }
+    }
+
+    fn send(&self, job: ChunkJob) {
+        if self.jobs.send(job).is_err() {
+            log::error!("chunk generation thread has stopped");
+        }
+    }
+}
+
+impl Drop for ChunkJobs {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+struct Worker {
+    world: ComputeWorld,
+    jobs: Receiver<ChunkJob>,
+    completed: Sender<(ChunkCoord, ChunkMeshes)>,
+    // highest priority first
+    pending: Vec<ChunkCoord>,
+}
+
+impl Worker {
+    fn run(mut self) {
+        loop {
+            // sleep until there is something to do, otherwise just pick up
+            // whatever arrived during the last batch
+            let job = if self.pending.is_empty() {
+                match self.jobs.recv() {
+                    Ok(job) => Some(job),
+                    Err(_) => return,
+                }
+            } else {
+                match self.jobs.try_recv() {
+                    Ok(job) => Some(job),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return,
+                }
+            };
+
+            match job {
+                Some(ChunkJob::Request(coords)) => {
+                    let requested: HashSet<ChunkCoord> = coords.iter().copied().collect();
+                    self.pending.retain(|coord| !requested.contains(coord));
+                    self.pending.splice(0..0, coords);
+                }
+                Some(ChunkJob::Cancel(coords)) => {
+                    let cancelled: HashSet<ChunkCoord> = coords.into_iter().collect();
+                    self.pending.retain(|coord| !cancelled.contains(coord));
+                }
+                Some(ChunkJob::Store(coord, chunk)) => self.world.store(coord, chunk),
+                Some(ChunkJob::Shutdown) => return,
+                None => self.generate(),
+            }
+        }
+    }
+
+    fn generate(&mut self) {
+        let chunks = self.world.load_chunks(&self.pending);
+        self.pending.retain(|coord| !chunks.contains_key(coord));
+        log::debug!("Chunk cache: {:?}", self.world.cache_stats());
+
+        for chunk in chunks {
+            if self.completed.send(chunk).is_err() {
+                // nobody is listening any more
+                self.pending.clear();
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+use wgpu::util::DeviceExt;
+
+use crate::lib::options::{ErosionOptions, Options};
+use crate::world::ChunkDimensions;
+
+/// Bytes of a `Cell` in `gen_terrain.wgsl`.
+const CELL_SIZE: u64 = 12;
+/// Bytes of a `Flow` in `gen_terrain.wgsl`.
+const FLOW_SIZE: u64 = 48;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct ErosionUniform {
+    options: ErosionOptions,
+    margin: u32,
+    lod_count: u32,
+    _padding: [u32; 2],
+}
+
+/// The erosion passes of `gen_terrain.wgsl`, run over a batch of chunks
+/// straight after `gen_terrain_compute` generated it.
+///
+/// Each chunk is eroded on its own grid reaching `ErosionOptions::margin`
+/// cells past its edges, then every level of detail is moved onto it.
+pub struct ErosionPipeline {
+    options: ErosionOptions,
+    chunk_dimensions: ChunkDimensions,
+    lod_count: u32,
+    uniform_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    init_pipeline: wgpu::ComputePipeline,
+    flow_pipeline: wgpu::ComputePipeline,
+    settle_pipeline: wgpu::ComputePipeline,
+    apply_pipeline: wgpu::ComputePipeline,
+}
+
+impl ErosionPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        shader: &wgpu::ShaderModule,
+        options: &Options,
+        erosion: ErosionOptions,
+    ) -> Self {
+        let lod_count = options.lod_count();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ErosionPipeline: Uniform"),
+            contents: bytemuck::bytes_of(&ErosionUniform {
+                options: erosion,
+                margin: erosion.margin(),
+                lod_count,
+                _padding: [0; 2],
+            }),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // same slots as the generation layout, without the indices
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("ErosionPipeline::Layout"),
+            entries: &[
+                storage(0, true),
+                storage(1, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(4, false),
+                storage(5, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ErosionPipeline::PipelineLayout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: shader,
+                entry_point,
+            })
+        };
+
+        Self {
+            options: erosion,
+            chunk_dimensions: options.chunk_dimensions,
+            lod_count,
+            uniform_buffer,
+            layout,
+            init_pipeline: pipeline("erosion_init"),
+            flow_pipeline: pipeline("erosion_flow"),
+            settle_pipeline: pipeline("erosion_settle"),
+            apply_pipeline: pipeline("erosion_apply"),
+        }
+    }
+
+    /// Cells in the grid eroded for one chunk.
+    fn domain_cells(&self) -> u64 {
+        let resolution = self.chunk_dimensions.resolution;
+        let margin = self.options.margin();
+        (resolution.x + 2 * margin + 1) as u64 * (resolution.y + 2 * margin + 1) as u64
+    }
+
+    /// Bytes per chunk of the largest buffer the passes use, which limits how
+    /// many chunks fit in a batch.
+    pub fn chunk_bytes(&self) -> u64 {
+        self.domain_cells() * FLOW_SIZE
+    }
+
+    /// Records the passes eroding `chunk_count` chunks whose meshes
+    /// `gen_terrain_compute` has written to `vertices` from `jobs`.
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        jobs: &wgpu::Buffer,
+        vertices: &wgpu::Buffer,
+        chunk_count: u32,
+    ) {
+        let domain_cells = self.domain_cells() * chunk_count as u64;
+        let cells = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ErosionPipeline: Cells"),
+            size: domain_cells * CELL_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let flows = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ErosionPipeline: Flows"),
+            size: domain_cells * FLOW_SIZE,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("ErosionPipeline: BindGroup"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: jobs.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: cells.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: flows.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("ErosionPipeline: ComputePass"),
+        });
+        cpass.set_bind_group(0, &bind_group, &[]);
+        let cell_groups = (self.domain_cells() as f32 / 64.0).ceil() as u32;
+
+        cpass.set_pipeline(&self.init_pipeline);
+        cpass.dispatch_workgroups(cell_groups, chunk_count, 1);
+        for _ in 0..self.options.iterations {
+            cpass.set_pipeline(&self.flow_pipeline);
+            cpass.dispatch_workgroups(cell_groups, chunk_count, 1);
+            cpass.set_pipeline(&self.settle_pipeline);
+            cpass.dispatch_workgroups(cell_groups, chunk_count, 1);
+        }
+
+        cpass.set_pipeline(&self.apply_pipeline);
+        let num_vertices = self.chunk_dimensions.num_vertices();
+        cpass.dispatch_workgroups(
+            (num_vertices as f32 / 64.0).ceil() as u32,
+            chunk_count * self.lod_count,
+            1,
+        );
+    }
+}
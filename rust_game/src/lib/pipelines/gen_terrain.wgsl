@@ -30,13 +30,25 @@ fn snoise2(v: vec2<f32>) -> f32 {
 }
 
 
-fn fbm(p: vec2<f32>) -> f32 {
+// PCG hash, from "Hash Functions for GPU Rendering" (Jarzynski & Olano, 2020)
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+fn fbm(p: vec2<f32>, seed: u32) -> f32 {
     let NUM_OCTAVES: u32 = 5u;
-    var x = p * 0.01;
+    // each seed samples the noise somewhere else within its 289 unit period,
+    // and turns it by a different angle between octaves
+    let h = pcg(seed);
+    let seed_shift = vec2<f32>(f32(h & 0xffffu), f32(h >> 16u)) / 65536.0 * 289.0;
+    let angle = 0.5 + f32(pcg(h) & 0xffffu) / 65536.0 * 6.2831853;
+    var x = p * 0.01 + seed_shift;
     var v = 0.0;
     var a = 0.5;
     let shift = vec2<f32>(100.0);
-    let cs = vec2<f32>(cos(0.5), sin(0.5));
+    let cs = vec2<f32>(cos(angle), sin(angle));
     let rot = mat2x2<f32>(cs.x, cs.y, -cs.y, cs.x);
 
     for (var i = 0u; i < NUM_OCTAVES; i = i + 1u) {
@@ -48,14 +60,86 @@ fn fbm(p: vec2<f32>) -> f32 {
     return v;
 }
 
+// ============================
+// Biomes
+// ============================
+
+// Biome ids, stored in the vertex padding and read back by terrain.wgsl
+// and the CPU copy in noise.rs
+const BIOME_PLAINS: u32 = 0u;
+const BIOME_MOUNTAINS: u32 = 1u;
+const BIOME_DESERT: u32 = 2u;
+const BIOME_SNOW: u32 = 3u;
+const BIOME_OCEAN: u32 = 4u;
+
+// Temperature and moisture in [0, 1], changing over hundreds of units
+// rather than tens like the terrain itself.
+fn climate(p: vec2<f32>, seed: u32) -> vec2<f32> {
+    let h = pcg(seed ^ 0x9e3779b9u);
+    let seed_shift = vec2<f32>(f32(h & 0xffffu), f32(h >> 16u)) / 65536.0 * 289.0;
+    let x = p * 0.002 + seed_shift;
+    let temperature = snoise2(x) + 0.5 * snoise2(x * 2.0 + vec2<f32>(31.0, 17.0));
+    let moisture = snoise2(x + vec2<f32>(113.0, 57.0)) + 0.5 * snoise2(x * 2.0 + vec2<f32>(71.0, 5.0));
+    return clamp(vec2<f32>(temperature, moisture) * 0.5 + 0.5, vec2<f32>(0.0), vec2<f32>(1.0));
+}
+
+// Biome at a corner of the climate table: temperature cold, temperate or hot
+// by moisture dry, average or wet.
+fn climate_biome(cell: vec2<u32>) -> u32 {
+    // cold: snow, snow, ocean
+    // temperate: mountains, plains, ocean
+    // hot: desert, plains, ocean
+    switch cell.x * 3u + cell.y {
+        case 0u, 1u: { return BIOME_SNOW; }
+        case 3u: { return BIOME_MOUNTAINS; }
+        case 4u, 7u: { return BIOME_PLAINS; }
+        case 6u: { return BIOME_DESERT; }
+        default: { return BIOME_OCEAN; }
+    }
+}
+
+// Lowest and highest terrain the noise maps to in a biome
+fn biome_heights(biome: u32) -> vec2<f32> {
+    if (biome == BIOME_MOUNTAINS) { return vec2<f32>(0.0, 40.0); }
+    if (biome == BIOME_DESERT) { return vec2<f32>(-1.0, 8.0); }
+    if (biome == BIOME_SNOW) { return vec2<f32>(2.0, 25.0); }
+    if (biome == BIOME_OCEAN) { return vec2<f32>(-25.0, -5.0); }
+    return vec2<f32>(-2.0, 6.0);
+}
+
+// Biome of the nearest corner of the climate table
+fn biome(climate: vec2<f32>) -> u32 {
+    return climate_biome(vec2<u32>(round(climate * 2.0)));
+}
+
+// Height range blended between the four surrounding corners of the climate
+// table, so the terrain never steps where the biome changes
+fn terrain_heights(climate: vec2<f32>) -> vec2<f32> {
+    let g = climate * 2.0;
+    let cell = min(vec2<u32>(g), vec2<u32>(1u));
+    let t = g - vec2<f32>(cell);
+    let h00 = biome_heights(climate_biome(cell));
+    let h10 = biome_heights(climate_biome(cell + vec2<u32>(1u, 0u)));
+    let h01 = biome_heights(climate_biome(cell + vec2<u32>(0u, 1u)));
+    let h11 = biome_heights(climate_biome(cell + vec2<u32>(1u, 1u)));
+    return mix(mix(h00, h10, t.x), mix(h01, h11, t.x), t.y);
+}
+
 struct ChunkData {
     chunk_size: vec2<u32>,
-    chunk_corner: vec2<i32>,
-    min_max_height: vec2<f32>,
+    chunk_corner: vec2<f32>,
+    chunk_extent: vec2<f32>,
+    skirt_depth: f32,
+    // where this mesh starts in the shared vertex and index buffers
+    vertex_offset: u32,
+    index_offset: u32,
+    seed: u32,
 }
 
 struct Vertex {
     @location(0) position: vec3<f32>,
+    // fills the padding after the position
+    @location(2) biome: u32,
     @location(1) normal: vec3<f32>,
 }
 
@@ -67,56 +151,153 @@ struct IndexBuffer {
     data: array<u32>,
 }
 
-@group(0) @binding(0) var<uniform> chunk_data: ChunkData;
+// one entry per mesh generated by the dispatch, picked by workgroup y
+@group(0) @binding(0) var<storage, read> jobs: array<ChunkData>;
 @group(0) @binding(1) var<storage, read_write> vertices: VertexBuffer;
 @group(0) @binding(2) var<storage, read_write> indices: IndexBuffer;
 
-fn terrain_point(p: vec2<f32>, min_max_height: vec2<f32>) -> vec3<f32> {
+var<private> chunk_data: ChunkData;
+
+fn terrain_point(p: vec2<f32>, seed: u32) -> vec3<f32> {
+    let heights = terrain_heights(climate(p, seed));
+    // fbm is centred on zero, the middle of the range should be too
     return vec3<f32>(
         p.x,
-        mix(min_max_height.x, min_max_height.y, fbm(p)),
+        mix(heights.x, heights.y, fbm(p, seed) + 0.5),
         p.y,
     );
 }
 
-fn terrain_vertex(p: vec2<f32>, min_max_height: vec2<f32>) -> Vertex {
-    let v = terrain_point(p, min_max_height);
+fn terrain_vertex(p: vec2<f32>, seed: u32) -> Vertex {
+    let v = terrain_point(p, seed);
 
-    let tpx = terrain_point(p + vec2<f32>(0.1, 0.0), min_max_height) - v;
-    let tpz = terrain_point(p + vec2<f32>(0.0, 0.1), min_max_height) - v;
-    let tnx = terrain_point(p + vec2<f32>(-0.1, 0.0), min_max_height) - v;
-    let tnz = terrain_point(p + vec2<f32>(0.0, -0.1), min_max_height) - v;
+    let tpx = terrain_point(p + vec2<f32>(0.1, 0.0), seed) - v;
+    let tpz = terrain_point(p + vec2<f32>(0.0, 0.1), seed) - v;
+    let tnx = terrain_point(p + vec2<f32>(-0.1, 0.0), seed) - v;
+    let tnz = terrain_point(p + vec2<f32>(0.0, -0.1), seed) - v;
 
     let pn = normalize(cross(tpz, tpx));
     let nn = normalize(cross(tnz, tnx));
 
     let n = (pn + nn) * 0.5;
 
-    return Vertex(v, n);
+    return Vertex(v, biome(climate(p, seed)), n);
 }
 
-fn index_to_p(vert_index: u32, chunk_size: vec2<u32>, chunk_corner: vec2<i32>) -> vec2<f32> {
+fn index_to_p(vert_index: u32, chunk_size: vec2<u32>, chunk_corner: vec2<f32>, chunk_extent: vec2<f32>) -> vec2<f32> {
+    let spacing = chunk_extent / vec2<f32>(chunk_size);
     return vec2(
-        f32(vert_index) % f32(chunk_size.x + 1u),
+        f32(vert_index % (chunk_size.x + 1u)),
         f32(vert_index / (chunk_size.x + 1u)),
-    ) + vec2<f32>(chunk_corner);
+    ) * spacing + chunk_corner;
+}
+
+// Skirts hang a strip of geometry below each chunk edge so the cracks between
+// neighbouring chunks of different LOD are never see-through. Skirt vertices
+// follow the grid vertices edge by edge: z min, z max, x min, x max.
+struct SkirtVertex {
+    // 0 to 3, or 4 past the last skirt vertex
+    edge: u32,
+    // position along the edge
+    i: u32,
+    // grid vertex it hangs below
+    top: u32,
+    // grid vertices from one along the edge to the next
+    step: u32,
+    segments: u32,
+}
+
+fn skirt_vertex(skirt_index: u32, chunk_size: vec2<u32>) -> SkirtVertex {
+    let row = chunk_size.x + 1u;
+    let column = chunk_size.y + 1u;
+
+    var edge: u32;
+    var i: u32;
+    if (skirt_index < row) {
+        edge = 0u;
+        i = skirt_index;
+    } else if (skirt_index < 2u * row) {
+        edge = 1u;
+        i = skirt_index - row;
+    } else if (skirt_index < 2u * row + column) {
+        edge = 2u;
+        i = skirt_index - 2u * row;
+    } else if (skirt_index < 2u * (row + column)) {
+        edge = 3u;
+        i = skirt_index - 2u * row - column;
+    } else {
+        return SkirtVertex(4u, 0u, 0u, 0u, 0u);
+    }
+
+    switch edge {
+        case 0u: { return SkirtVertex(edge, i, i, 1u, chunk_size.x); }
+        case 1u: { return SkirtVertex(edge, i, chunk_size.y * row + i, 1u, chunk_size.x); }
+        case 2u: { return SkirtVertex(edge, i, i * row, row, chunk_size.y); }
+        default: { return SkirtVertex(edge, i, i * row + chunk_size.x, row, chunk_size.y); }
+    }
+}
+
+fn gen_skirt(skirt_index: u32) {
+    let chunk_size = chunk_data.chunk_size;
+    let grid_vertices = (chunk_size.x + 1u) * (chunk_size.y + 1u);
+    let skirt = skirt_vertex(skirt_index, chunk_size);
+    if (skirt.edge > 3u) { return; }
+
+    let p = index_to_p(skirt.top, chunk_size, chunk_data.chunk_corner, chunk_data.chunk_extent);
+    var vertex = terrain_vertex(p, chunk_data.seed);
+    vertex.position.y = vertex.position.y - chunk_data.skirt_depth;
+    vertices.data[chunk_data.vertex_offset + grid_vertices + skirt_index] = vertex;
+
+    if (skirt.i >= skirt.segments) { return; }
+
+    // every edge before this one has one more vertex than it has segments
+    let start_index = chunk_data.index_offset + chunk_size.x * chunk_size.y * 6u + (skirt_index - skirt.edge) * 6u;
+    let a = skirt.top;
+    let b = skirt.top + skirt.step;
+    let a_low = grid_vertices + skirt_index;
+    let b_low = a_low + 1u;
+
+    // wind each strip so it faces away from the chunk
+    if (skirt.edge == 0u || skirt.edge == 3u) {
+        indices.data[start_index] = a;
+        indices.data[start_index + 1u] = b;
+        indices.data[start_index + 2u] = b_low;
+        indices.data[start_index + 3u] = a;
+        indices.data[start_index + 4u] = b_low;
+        indices.data[start_index + 5u] = a_low;
+    } else {
+        indices.data[start_index] = a;
+        indices.data[start_index + 1u] = b_low;
+        indices.data[start_index + 2u] = b;
+        indices.data[start_index + 3u] = a;
+        indices.data[start_index + 4u] = a_low;
+        indices.data[start_index + 5u] = b_low;
+    }
 }
 
 @compute @workgroup_size(64)
 fn gen_terrain_compute(
     @builtin(global_invocation_id) gid: vec3<u32>
 ) {
+    chunk_data = jobs[gid.y];
+
     // Create vert_component
     let vert_index = gid.x;
+    let grid_vertices = (chunk_data.chunk_size.x + 1u) * (chunk_data.chunk_size.y + 1u);
+
+    if (vert_index >= grid_vertices) {
+        gen_skirt(vert_index - grid_vertices);
+        return;
+    }
 
-    let p = index_to_p(vert_index, chunk_data.chunk_size, chunk_data.chunk_corner);
+    let p = index_to_p(vert_index, chunk_data.chunk_size, chunk_data.chunk_corner, chunk_data.chunk_extent);
 
-    vertices.data[vert_index] = terrain_vertex(p, chunk_data.min_max_height);
+    vertices.data[chunk_data.vertex_offset + vert_index] = terrain_vertex(p, chunk_data.seed);
 
     // Create indices
-    let start_index = gid.x * 6u; // using TriangleList
+    if (gid.x >= chunk_data.chunk_size.x * chunk_data.chunk_size.y) { return; }
 
-    if (start_index >= (chunk_data.chunk_size.x * chunk_data.chunk_size.y * 6u)) { return; }
+    let start_index = chunk_data.index_offset + gid.x * 6u; // using TriangleList
 
     let v00 = vert_index + gid.x / chunk_data.chunk_size.x;
     let v10 = v00 + 1u;
@@ -130,3 +311,451 @@ fn gen_terrain_compute(
     indices.data[start_index + 4u] = v11;
     indices.data[start_index + 5u] = v10;
 }
+
+// ============================
+// Erosion
+// ============================
+
+// Runs after gen_terrain_compute over the most detailed grid of every chunk
+// plus a margin of cells around it. Each pass only reads the cells next to
+// the one it writes, so with a wide enough margin nothing from the edge of
+// the grid reaches the chunk, every chunk comes out exactly as it would on
+// an endless grid, and neighbours tile. Mirrored in erosion.rs.
+
+struct Erosion {
+    iterations: u32,
+    rain: f32,
+    evaporation: f32,
+    sediment_capacity: f32,
+    erosion_rate: f32,
+    deposition_rate: f32,
+    talus: f32,
+    thermal_rate: f32,
+    margin: u32,
+    lod_count: u32,
+}
+
+struct Cell {
+    height: f32,
+    water: f32,
+    sediment: f32,
+}
+
+// what leaves a cell towards +x, -x, +z and -z
+struct Flow {
+    water: vec4<f32>,
+    sediment: vec4<f32>,
+    slide: vec4<f32>,
+}
+
+@group(0) @binding(3) var<uniform> erosion: Erosion;
+// one grid of cells per chunk, row by row along x
+@group(0) @binding(4) var<storage, read_write> cells: array<Cell>;
+@group(0) @binding(5) var<storage, read_write> flows: array<Flow>;
+
+fn domain_size(chunk_size: vec2<u32>) -> vec2<u32> {
+    return chunk_size + 2u * erosion.margin + 1u;
+}
+
+fn domain_index(chunk: u32, size: vec2<u32>, cell: vec2<u32>) -> u32 {
+    return (chunk * size.y + cell.y) * size.x + cell.x;
+}
+
+// Index of the cell next to `cell` towards +x, -x, +z or -z, or -1 past the
+// edge of the grid
+fn neighbour(chunk: u32, size: vec2<u32>, cell: vec2<u32>, direction: u32) -> i32 {
+    var n = vec2<i32>(cell);
+    switch direction {
+        case 0u: { n.x = n.x + 1; }
+        case 1u: { n.x = n.x - 1; }
+        case 2u: { n.y = n.y + 1; }
+        default: { n.y = n.y - 1; }
+    }
+    if (any(n < vec2<i32>(0)) || any(n >= vec2<i32>(size))) {
+        return -1;
+    }
+    return i32(domain_index(chunk, size, vec2<u32>(n)));
+}
+
+// World position of a cell, counted in cells from the world origin so every
+// chunk sharing a cell samples exactly the same point
+fn domain_point(cell: vec2<u32>) -> vec2<f32> {
+    let spacing = chunk_data.chunk_extent / vec2<f32>(chunk_data.chunk_size);
+    let coord = vec2<i32>(round(chunk_data.chunk_corner / chunk_data.chunk_extent));
+    let grid = coord * vec2<i32>(chunk_data.chunk_size) + vec2<i32>(cell) - i32(erosion.margin);
+    return vec2<f32>(grid) * spacing;
+}
+
+// Height once the water has gone and the sediment it carried has settled
+fn eroded_height(index: u32) -> f32 {
+    let cell = cells[index];
+    return cell.height + cell.sediment;
+}
+
+@compute @workgroup_size(64)
+fn erosion_init(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    chunk_data = jobs[gid.y * erosion.lod_count];
+    let size = domain_size(chunk_data.chunk_size);
+    if (gid.x >= size.x * size.y) { return; }
+
+    let cell = vec2<u32>(gid.x % size.x, gid.x / size.x);
+    let height = terrain_point(domain_point(cell), chunk_data.seed).y;
+    cells[domain_index(gid.y, size, cell)] = Cell(height, 0.0, 0.0);
+}
+
+// Works out what leaves each cell: water running down to lower water levels,
+// carrying its share of the sediment, and ground sliding off slopes steeper
+// than the talus slope
+@compute @workgroup_size(64)
+fn erosion_flow(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    chunk_data = jobs[gid.y * erosion.lod_count];
+    let size = domain_size(chunk_data.chunk_size);
+    if (gid.x >= size.x * size.y) { return; }
+
+    let cell = vec2<u32>(gid.x % size.x, gid.x / size.x);
+    let index = domain_index(gid.y, size, cell);
+    let spacing = chunk_data.chunk_extent / vec2<f32>(chunk_data.chunk_size);
+    let here = cells[index];
+    let water = here.water + erosion.rain;
+
+    var drop = vec4<f32>(0.0);
+    var excess = vec4<f32>(0.0);
+    for (var direction = 0u; direction < 4u; direction = direction + 1u) {
+        let n = neighbour(gid.y, size, cell, direction);
+        if (n < 0) { continue; }
+        let there = cells[n];
+        drop[direction] = max(here.height + water - (there.height + there.water + erosion.rain), 0.0);
+        let distance = select(spacing.y, spacing.x, direction < 2u);
+        excess[direction] = max(here.height - there.height - erosion.talus * distance, 0.0);
+    }
+
+    var flow: Flow;
+    let total_drop = dot(drop, vec4<f32>(1.0));
+    if (total_drop > 0.0 && water > 0.0) {
+        // at most half the difference, so water levels meet rather than swap
+        flow.water = drop * (min(water, total_drop * 0.5) / total_drop);
+        flow.sediment = flow.water * (here.sediment / water);
+    }
+    let total_excess = dot(excess, vec4<f32>(1.0));
+    if (total_excess > 0.0) {
+        let slid = erosion.thermal_rate * max(max(excess.x, excess.y), max(excess.z, excess.w)) * 0.5;
+        flow.slide = excess * (slid / total_excess);
+    }
+    flows[index] = flow;
+}
+
+// Moves what erosion_flow worked out, then lets the water running through
+// each cell dig up or drop sediment and some of it dry up
+@compute @workgroup_size(64)
+fn erosion_settle(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    chunk_data = jobs[gid.y * erosion.lod_count];
+    let size = domain_size(chunk_data.chunk_size);
+    if (gid.x >= size.x * size.y) { return; }
+
+    let cell = vec2<u32>(gid.x % size.x, gid.x / size.x);
+    let index = domain_index(gid.y, size, cell);
+    let here = cells[index];
+    let out = flows[index];
+
+    var water = here.water + erosion.rain - dot(out.water, vec4<f32>(1.0));
+    var sediment = here.sediment - dot(out.sediment, vec4<f32>(1.0));
+    var height = here.height - dot(out.slide, vec4<f32>(1.0));
+    for (var direction = 0u; direction < 4u; direction = direction + 1u) {
+        let n = neighbour(gid.y, size, cell, direction);
+        if (n < 0) { continue; }
+        // the neighbour's flow back the way we came
+        let back = direction ^ 1u;
+        let inflow = flows[n];
+        water = water + inflow.water[back];
+        sediment = sediment + inflow.sediment[back];
+        height = height + inflow.slide[back];
+    }
+
+    let capacity = erosion.sediment_capacity * dot(out.water, vec4<f32>(1.0));
+    if (sediment > capacity) {
+        let deposited = erosion.deposition_rate * (sediment - capacity);
+        height = height + deposited;
+        sediment = sediment - deposited;
+    } else {
+        let eroded = erosion.erosion_rate * (capacity - sediment);
+        height = height - eroded;
+        sediment = sediment + eroded;
+    }
+
+    cells[index] = Cell(height, water * (1.0 - erosion.evaporation), sediment);
+}
+
+// Moves every vertex of every level of detail onto the eroded grid, with
+// normals from the eroded slopes. Dispatched like gen_terrain_compute.
+@compute @workgroup_size(64)
+fn erosion_apply(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    chunk_data = jobs[gid.y];
+    let chunk = gid.y / erosion.lod_count;
+    let level = gid.y % erosion.lod_count;
+    let chunk_size = chunk_data.chunk_size;
+    let row = chunk_size.x + 1u;
+    let grid_vertices = row * (chunk_size.y + 1u);
+
+    var top = gid.x;
+    var skirt_depth = 0.0;
+    if (gid.x >= grid_vertices) {
+        let skirt = skirt_vertex(gid.x - grid_vertices, chunk_size);
+        if (skirt.edge > 3u) { return; }
+        top = skirt.top;
+        skirt_depth = chunk_data.skirt_depth;
+    }
+
+    // lower levels of detail skip cells of the most detailed grid
+    let detailed = jobs[chunk * erosion.lod_count];
+    let size = domain_size(detailed.chunk_size);
+    let spacing = detailed.chunk_extent / vec2<f32>(detailed.chunk_size);
+    let cell = vec2<u32>(top % row, top / row) * (1u << level) + erosion.margin;
+
+    let dx = eroded_height(domain_index(chunk, size, cell + vec2<u32>(1u, 0u)))
+        - eroded_height(domain_index(chunk, size, cell - vec2<u32>(1u, 0u)));
+    let dz = eroded_height(domain_index(chunk, size, cell + vec2<u32>(0u, 1u)))
+        - eroded_height(domain_index(chunk, size, cell - vec2<u32>(0u, 1u)));
+
+    let index = chunk_data.vertex_offset + gid.x;
+    var vertex = vertices.data[index];
+    vertex.position.y = eroded_height(domain_index(chunk, size, cell)) - skirt_depth;
+    vertex.normal = normalize(vec3<f32>(-dx / (2.0 * spacing.x), 1.0, -dz / (2.0 * spacing.y)));
+    vertices.data[index] = vertex;
+}
+
+// ============================
+// Mesh bounds
+// ============================
+
+// Runs last over every mesh of a batch, finding its lowest and highest
+// vertex so chunks left on the GPU can be culled without reading them back.
+// Atomics only take integers, so heights are kept as bits that sort the
+// same way the floats do.
+
+@group(0) @binding(10) var<storage, read_write> mesh_bounds: array<atomic<u32>>;
+
+fn ordered_bits(x: f32) -> u32 {
+    let bits = bitcast<u32>(x);
+    // negative floats sort backwards as integers, so flip all their bits
+    return select(bits | 0x80000000u, ~bits, (bits & 0x80000000u) != 0u);
+}
+
+@compute @workgroup_size(64)
+fn mesh_bounds_compute(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    let job = jobs[gid.y];
+    let size = job.chunk_size;
+    let num_vertices = (size.x + 1u) * (size.y + 1u) + 2u * (size.x + size.y + 2u);
+    if (gid.x >= num_vertices) { return; }
+
+    let y = ordered_bits(vertices.data[job.vertex_offset + gid.x].position.y);
+    atomicMin(&mesh_bounds[gid.y * 2u], y);
+    atomicMax(&mesh_bounds[gid.y * 2u + 1u], y);
+}
+
+// ============================
+// Voxel terrain
+// ============================
+
+// A density field, solid where it is positive, polygonised with marching
+// cubes. Unlike the heightfield it can hold caves and overhangs, at the cost
+// of a vertex count only known once the surface has been found. Every
+// triangle gets its own three vertices, so there are no indices.
+
+struct VoxelJob {
+    // cells along x, y and z
+    size: vec3<u32>,
+    seed: u32,
+    // world position of the lowest corner
+    origin: vec3<f32>,
+    // where this mesh starts in the shared vertex buffer
+    vertex_offset: u32,
+    spacing: vec3<f32>,
+    // most vertices this mesh has room for
+    vertex_capacity: u32,
+    // where the density of this job starts
+    density_offset: u32,
+}
+
+// laid out as the arguments of an indirect draw of the job's vertices
+struct VoxelCount {
+    vertex_count: atomic<u32>,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+}
+
+@group(0) @binding(6) var<storage, read> voxel_jobs: array<VoxelJob>;
+// density at every corner of every cell, row by row along x then y
+@group(0) @binding(7) var<storage, read_write> density: array<f32>;
+@group(0) @binding(8) var<storage, read_write> voxel_counts: array<VoxelCount>;
+// edges crossed by the triangles of each case, packed by marching_cubes.rs
+@group(0) @binding(9) var<uniform> triangle_table: array<vec4<u32>, 128>;
+
+// mirrored by marching_cubes.rs
+var<private> CUBE_CORNERS: array<vec3<u32>, 8> = array<vec3<u32>, 8>(
+    vec3<u32>(0u, 0u, 0u),
+    vec3<u32>(1u, 0u, 0u),
+    vec3<u32>(1u, 0u, 1u),
+    vec3<u32>(0u, 0u, 1u),
+    vec3<u32>(0u, 1u, 0u),
+    vec3<u32>(1u, 1u, 0u),
+    vec3<u32>(1u, 1u, 1u),
+    vec3<u32>(0u, 1u, 1u),
+);
+
+var<private> CUBE_EDGES: array<vec2<u32>, 12> = array<vec2<u32>, 12>(
+    vec2<u32>(0u, 1u),
+    vec2<u32>(1u, 2u),
+    vec2<u32>(3u, 2u),
+    vec2<u32>(0u, 3u),
+    vec2<u32>(4u, 5u),
+    vec2<u32>(5u, 6u),
+    vec2<u32>(7u, 6u),
+    vec2<u32>(4u, 7u),
+    vec2<u32>(0u, 4u),
+    vec2<u32>(1u, 5u),
+    vec2<u32>(2u, 6u),
+    vec2<u32>(3u, 7u),
+);
+
+// keep in step with VOXEL_FLOOR and VOXEL_CEILING in voxel.rs
+const VOXEL_CAVE_FLOOR: f32 = -40.0;
+const VOXEL_ROOF: f32 = 62.0;
+
+// Random value in [-1, 1] for a point of the integer lattice
+fn lattice_value(cell: vec3<i32>, seed: u32) -> f32 {
+    let h = pcg(bitcast<u32>(cell.x) ^ pcg(bitcast<u32>(cell.y) ^ pcg(bitcast<u32>(cell.z) ^ seed)));
+    return f32(h >> 8u) / 8388608.0 - 1.0;
+}
+
+// Value noise, smoothly blending the lattice values around p
+fn value_noise3(p: vec3<f32>, seed: u32) -> f32 {
+    let cell = vec3<i32>(floor(p));
+    let f = fract(p);
+    let t = f * f * (3.0 - 2.0 * f);
+    let c000 = lattice_value(cell, seed);
+    let c100 = lattice_value(cell + vec3<i32>(1, 0, 0), seed);
+    let c010 = lattice_value(cell + vec3<i32>(0, 1, 0), seed);
+    let c110 = lattice_value(cell + vec3<i32>(1, 1, 0), seed);
+    let c001 = lattice_value(cell + vec3<i32>(0, 0, 1), seed);
+    let c101 = lattice_value(cell + vec3<i32>(1, 0, 1), seed);
+    let c011 = lattice_value(cell + vec3<i32>(0, 1, 1), seed);
+    let c111 = lattice_value(cell + vec3<i32>(1, 1, 1), seed);
+    return mix(
+        mix(mix(c000, c100, t.x), mix(c010, c110, t.x), t.y),
+        mix(mix(c001, c101, t.x), mix(c011, c111, t.x), t.y),
+        t.z,
+    );
+}
+
+fn value_fbm3(p: vec3<f32>, seed: u32) -> f32 {
+    return value_noise3(p, seed) * 0.65 + value_noise3(p * 2.03 + vec3<f32>(17.0), seed) * 0.35;
+}
+
+// Roughly the distance to the surface, positive inside the ground. The
+// heightfield is sampled through a warp that shifts with height, which
+// leans cliffs out over their feet, and two noise fields carve tunnels
+// where both are close to zero.
+fn voxel_density(p: vec3<f32>, seed: u32) -> f32 {
+    let warp = vec2<f32>(
+        value_fbm3(p * 0.04, seed ^ 0x68bc21ebu),
+        value_fbm3(p * 0.04, seed ^ 0x02e5be93u),
+    );
+    let ground = terrain_point(p.xz + warp * 6.0, seed).y - p.y;
+
+    let tunnel = length(vec2<f32>(
+        value_fbm3(p * 0.03, seed ^ 0x1b873593u),
+        value_fbm3(p * 0.03, seed ^ 0xcc9e2d51u),
+    ));
+    // nothing is carved near the floor of the volume, and nothing stands
+    // above its roof, either of which would leave holes
+    let cave = max((tunnel - 0.05) * 40.0, VOXEL_CAVE_FLOOR - p.y);
+    return min(min(ground, cave), VOXEL_ROOF - p.y);
+}
+
+// Surface normal from the slope of the density, pointing out of the ground
+fn voxel_normal(p: vec3<f32>, seed: u32) -> vec3<f32> {
+    let e = 0.1;
+    return -normalize(vec3<f32>(
+        voxel_density(p + vec3<f32>(e, 0.0, 0.0), seed) - voxel_density(p - vec3<f32>(e, 0.0, 0.0), seed),
+        voxel_density(p + vec3<f32>(0.0, e, 0.0), seed) - voxel_density(p - vec3<f32>(0.0, e, 0.0), seed),
+        voxel_density(p + vec3<f32>(0.0, 0.0, e), seed) - voxel_density(p - vec3<f32>(0.0, 0.0, e), seed),
+    ));
+}
+
+fn density_index(job: VoxelJob, corner: vec3<u32>) -> u32 {
+    let points = job.size + 1u;
+    return job.density_offset + (corner.z * points.y + corner.y) * points.x + corner.x;
+}
+
+// Samples the density at every corner of every cell of a job
+@compute @workgroup_size(64)
+fn voxel_density_compute(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    let job = voxel_jobs[gid.y];
+    let points = job.size + 1u;
+    if (gid.x >= points.x * points.y * points.z) { return; }
+
+    let corner = vec3<u32>(gid.x % points.x, gid.x / points.x % points.y, gid.x / (points.x * points.y));
+    let p = job.origin + vec3<f32>(corner) * job.spacing;
+    density[density_index(job, corner)] = voxel_density(p, job.seed);
+}
+
+// Edge of the slot'th vertex of a case, or 15 past its last triangle
+fn case_edge(case_index: u32, slot: u32) -> u32 {
+    let word = triangle_table[case_index / 2u][case_index % 2u * 2u + slot / 8u];
+    return (word >> (slot % 8u * 4u)) & 15u;
+}
+
+// Cuts the surface through each cell into triangles, claiming room for them
+// at the end of the job's mesh
+@compute @workgroup_size(64)
+fn voxel_polygonise_compute(
+    @builtin(global_invocation_id) gid: vec3<u32>
+) {
+    let job = voxel_jobs[gid.y];
+    let size = job.size;
+    if (gid.x >= size.x * size.y * size.z) { return; }
+
+    let cell = vec3<u32>(gid.x % size.x, gid.x / size.x % size.y, gid.x / (size.x * size.y));
+    var values: array<f32, 8>;
+    var case_index = 0u;
+    for (var i = 0u; i < 8u; i = i + 1u) {
+        values[i] = density[density_index(job, cell + CUBE_CORNERS[i])];
+        if (values[i] > 0.0) {
+            case_index = case_index | (1u << i);
+        }
+    }
+
+    var vertex_count = 0u;
+    while (vertex_count < 15u && case_edge(case_index, vertex_count) != 15u) {
+        vertex_count = vertex_count + 3u;
+    }
+    if (vertex_count == 0u) { return; }
+
+    // the count keeps growing past the capacity, so the CPU can tell the
+    // mesh was cut short
+    let first = atomicAdd(&voxel_counts[gid.y].vertex_count, vertex_count);
+    if (first + vertex_count > job.vertex_capacity) { return; }
+
+    for (var slot = 0u; slot < vertex_count; slot = slot + 1u) {
+        let edge = CUBE_EDGES[case_edge(case_index, slot)];
+        let a = values[edge.x];
+        let b = values[edge.y];
+        let corner = vec3<f32>(cell + CUBE_CORNERS[edge.x]);
+        let along = vec3<f32>(CUBE_CORNERS[edge.y] - CUBE_CORNERS[edge.x]);
+        let p = job.origin + (corner + along * (a / (a - b))) * job.spacing;
+        vertices.data[job.vertex_offset + first + slot] = Vertex(p, biome(climate(p.xz, job.seed)), voxel_normal(p, job.seed));
+    }
+}
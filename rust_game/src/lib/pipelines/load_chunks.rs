@@ -1,152 +1,452 @@
-use std::{
-  collections::HashMap,
-  mem::size_of_val,
-};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{mpsc, Arc};
 
-use cgmath::Vector2;
-use crate::lib::model;
+use wgpu::util::DeviceExt;
+
+use crate::lib::{frustum::Aabb, options::Options};
+use super::chunk_cache::{CacheStats, ChunkCache};
+use super::erosion::ErosionPipeline;
+use super::mesh_bounds::MeshBoundsPipeline;
+use crate::world::noise::{PackedVertex, TerrainVertex};
+use crate::world::{ChunkCoord, ChunkDimensions, ChunkSlot, RegionStore, TerrainGenerator};
+
+/// Most chunks generated in one call to `ComputeWorld::load_chunks`, so
+/// new requests and cancellations are picked up between batches.
+const CHUNKS_PER_BATCH: usize = 64;
 
 #[derive(Clone)]
 pub struct RawBufferData {
-    pub vertex_data: [u8; 34848],
-    pub index_data: [u8; 24576],
+    pub vertex_data: Vec<u8>,
+    pub index_data: Vec<u8>,
+}
+
+impl RawBufferData {
+    pub fn vertex(&self, index: u32) -> TerrainVertex {
+        let start = index as usize * ChunkDimensions::VERTEX_SIZE as usize;
+        let end = start + ChunkDimensions::VERTEX_SIZE as usize;
+        bytemuck::pod_read_unaligned::<PackedVertex>(&self.vertex_data[start..end]).into()
+    }
+
+    pub fn set_vertex(&mut self, index: u32, vertex: TerrainVertex) {
+        let start = index as usize * ChunkDimensions::VERTEX_SIZE as usize;
+        let end = start + ChunkDimensions::VERTEX_SIZE as usize;
+        self.vertex_data[start..end].copy_from_slice(bytemuck::bytes_of(&PackedVertex::from(vertex)));
+    }
+
+    pub fn index(&self, i: u32) -> u32 {
+        let start = i as usize * ChunkDimensions::INDEX_SIZE as usize;
+        let end = start + ChunkDimensions::INDEX_SIZE as usize;
+        bytemuck::pod_read_unaligned(&self.index_data[start..end])
+    }
+
+    pub fn num_indices(&self) -> u32 {
+        self.index_data.len() as u32 / ChunkDimensions::INDEX_SIZE
+    }
+
+    /// Heights of the lowest and highest vertex, or `(f32::MAX, f32::MIN)`
+    /// without any vertices.
+    pub fn height_range(&self) -> (f32, f32) {
+        let num_vertices = self.vertex_data.len() as u32 / ChunkDimensions::VERTEX_SIZE;
+        (0..num_vertices)
+            .map(|i| self.vertex(i).position.y)
+            .fold((f32::MAX, f32::MIN), |(min, max), y| (min.min(y), max.max(y)))
+    }
+}
+
+/// CPU copy of every level of detail of a chunk, most detailed first.
+#[derive(Clone)]
+pub struct RawChunkData {
+    pub lods: Vec<RawBufferData>,
+    /// Lowest and highest vertex of any level of detail, skirts included,
+    /// bounding the chunk for culling.
+    pub height_range: (f32, f32),
+}
+
+impl RawChunkData {
+    /// Measures the height range of the meshes as they are generated, so
+    /// it never has to be worked out on the render thread.
+    pub fn new(lods: Vec<RawBufferData>) -> Self {
+        let height_range = lods
+            .iter()
+            .map(RawBufferData::height_range)
+            .fold((f32::MAX, f32::MIN), |(min, max), (low, high)| (min.min(low), max.max(high)));
+        Self { lods, height_range }
+    }
+
+    pub fn size_bytes(&self) -> usize {
+        self.lods
+            .iter()
+            .map(|lod| lod.vertex_data.len() + lod.index_data.len())
+            .sum()
+    }
+}
+
+/// Vertices and indices of one level of detail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshSize {
+    pub num_vertices: u32,
+    pub num_indices: u32,
+}
+
+/// Every level of detail of a chunk generated on the GPU and never read
+/// back, most detailed first, one after another in buffers of its own.
+pub struct GpuChunkData {
+    pub vertex_buffer: wgpu::Buffer,
+    pub index_buffer: wgpu::Buffer,
+    pub lods: Vec<MeshSize>,
+    /// Measured on the GPU, as for `RawChunkData::height_range`.
+    pub height_range: (f32, f32),
+}
+
+/// A loaded chunk, on whichever side of the bus it happens to be.
+#[derive(Clone)]
+pub enum ChunkMeshes {
+    /// Loaded from disk, built on the CPU, or read back.
+    Cpu(RawChunkData),
+    /// Left on the GPU where it was generated, until something needs it on
+    /// the CPU.
+    Gpu(Arc<GpuChunkData>),
+}
+
+impl ChunkMeshes {
+    pub fn height_range(&self) -> (f32, f32) {
+        match self {
+            Self::Cpu(data) => data.height_range,
+            Self::Gpu(data) => data.height_range,
+        }
+    }
+
+    pub fn mesh_sizes(&self) -> Vec<MeshSize> {
+        match self {
+            Self::Cpu(data) => data
+                .lods
+                .iter()
+                .map(|lod| MeshSize {
+                    num_vertices: lod.vertex_data.len() as u32 / ChunkDimensions::VERTEX_SIZE,
+                    num_indices: lod.index_data.len() as u32 / ChunkDimensions::INDEX_SIZE,
+                })
+                .collect(),
+            Self::Gpu(data) => data.lods.clone(),
+        }
+    }
+
+    /// Bytes held, in main or GPU memory.
+    pub fn size_bytes(&self) -> usize {
+        match self {
+            Self::Cpu(data) => data.size_bytes(),
+            Self::Gpu(data) => (data.vertex_buffer.size() + data.index_buffer.size()) as usize,
+        }
+    }
 }
 
 pub struct Chunk {
-  pub mesh: model::Mesh,
+  /// Every level of detail, in the `ChunkArena`.
+  pub slot: ChunkSlot,
+  /// Level of detail currently drawn.
+  pub lod: usize,
+  /// Whether the skirts need drawing to hide cracks against neighbours
+  /// drawn at another level of detail.
+  pub skirts: bool,
+  /// Box around every level of detail, for culling.
+  pub bounds: Aabb,
 }
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 struct ChunkData {
     chunk_size: [u32; 2],
-    chunk_corner: [i32; 2],
-    min_max_height: [f32; 2],
+    chunk_corner: [f32; 2],
+    chunk_extent: [f32; 2],
+    skirt_depth: f32,
+    /// Where this mesh starts in the shared vertex and index buffers.
+    vertex_offset: u32,
+    index_offset: u32,
+    seed: u32,
 }
 
 pub struct ComputeWorld {
-  pub chunks: HashMap<String, RawBufferData>,
-  chunk_size: Vector2<u32>,
+  cache: ChunkCache,
+  regions: Option<RegionStore>,
+  generator: Box<dyn TerrainGenerator>,
+  device: Arc<wgpu::Device>,
+  queue: Arc<wgpu::Queue>,
 }
 
 impl ComputeWorld {
-  pub fn new(chunk_size: Vector2<u32>) -> Self {
+  /// The device and queue read back generated chunks that have to be
+  /// saved, and must be the ones `generator` uses.
+  pub fn new(
+      options: &Options,
+      generator: Box<dyn TerrainGenerator>,
+      device: Arc<wgpu::Device>,
+      queue: Arc<wgpu::Queue>,
+  ) -> Self {
+      let save_dir = options.save_dir.as_ref().filter(|_| generator.saves_chunks());
+      let regions = save_dir.and_then(|dir| {
+          RegionStore::open(
+              dir,
+              &options.chunk_dimensions,
+              options.lod_count(),
+              options.seed,
+              options.erosion,
+          )
+              .map_err(|e| log::error!("chunks will not be saved: {:#}", e))
+              .ok()
+      });
+
       Self {
-          chunks: HashMap::new(),
-          chunk_size,
+          cache: ChunkCache::new(options.chunk_cache_size, options.chunk_cache_bytes),
+          regions,
+          generator,
+          device,
+          queue,
       }
   }
 
-  pub async fn load_chunks(
-      &mut self,
-      device: &wgpu::Device,
-      queue: &wgpu::Queue,
-      pipeline: &ComputeWorldPipeline,
-      anchor_coords: HashMap<String, Vec<i32>>
-  ) {
+  pub fn cache_stats(&self) -> CacheStats {
+      self.cache.stats()
+  }
+
+  /// Loads requested chunks in the given order, reading or generating at
+  /// most one batch of them. Chunks still in the cache are returned as well.
+  ///
+  /// Chunks are looked up in the cache, then the save directory, and only
+  /// generated if neither has them. The generator may leave them on the
+  /// GPU; ones to be saved are then read back here, on the worker thread,
+  /// and still handed on as they were generated.
+  pub fn load_chunks(&mut self, requested_chunks: &[ChunkCoord]) -> HashMap<ChunkCoord, ChunkMeshes> {
+        let batch_size = self.generator.max_batch_size().min(CHUNKS_PER_BATCH);
         let mut new_chunks = HashMap::new();
-        for (chunk_key, anchor_coords) in anchor_coords.iter(){
-            if let Some(chunk) = self.chunks.remove(chunk_key) {
-                // chunk exists
-                new_chunks.insert(chunk_key.clone(), chunk);
+        let mut missing = Vec::new();
+        let mut loaded = 0;
+        for &coord in requested_chunks {
+            if !self.cache.contains(&coord) && missing.len() + loaded == batch_size {
+                // out of budget for this batch, leave the rest for the next one
+                break;
+            }
+            if let Some(chunk) = self.cache.get(&coord) {
+                // chunk was generated before
+                new_chunks.insert(coord, chunk.clone());
+            } else if let Some(chunk) = self.load_saved(coord) {
+                // chunk was saved by an earlier run
+                loaded += 1;
+                let chunk = ChunkMeshes::Cpu(chunk);
+                self.cache.insert(coord, chunk.clone());
+                new_chunks.insert(coord, chunk);
             } else {
-                // chunk does not exist, generate
-                let new_chunk = pipeline.gen_chunk(&device, &queue, Vector2::new(
-                    anchor_coords[0], anchor_coords[1]
-                ));
-                let num_vertices = (self.chunk_size.x + 1) * (self.chunk_size.y + 1);
-                let num_indices = self.chunk_size.x * self.chunk_size.y;
-                let vertex_staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                    label: Some("Vertices"),
-                    size: (num_vertices * 8 * std::mem::size_of::<f32>() as u32) as _,
-                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-                    mapped_at_creation: false,
-                });
-
-                let index_staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-                    label: Some("Indices"),
-                    size: (num_indices * 6 * std::mem::size_of::<u32>() as u32) as _,
-                    usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
-                    mapped_at_creation: false,
-                });
-
-                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Render Encoder"),
-                });
-
-                encoder.copy_buffer_to_buffer(
-                    &new_chunk.mesh.vertex_buffer, 
-                    0, 
-                    &vertex_staging_buffer, 
-                    0, 
-                    (num_vertices * 8 * std::mem::size_of::<f32>() as u32) as _,
-                );
-                queue.submit(Some(encoder.finish()));
-
-                let buffer_slice = vertex_staging_buffer.slice(..);
-                let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
-                buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
-                device.poll(wgpu::Maintain::Wait);
-
-                let mut vertex_data: [u8; 34848] = [0; 34848];
-                if let Some(Ok(())) = receiver.receive().await {
-                    let data = buffer_slice.get_mapped_range();
-                    vertex_data.copy_from_slice(bytemuck::cast_slice(&data));
-                    drop(data);
-                    vertex_staging_buffer.unmap();
-                } else {
-                    panic!("failed to ingest vertex data!")
-                }
-
-                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Render Encoder"),
-                });
+                missing.push(coord);
+            }
+        }
 
-                encoder.copy_buffer_to_buffer(
-                    &new_chunk.mesh.index_buffer, 
-                    0, 
-                    &index_staging_buffer, 
-                    0, 
-                    (num_indices * 6 * std::mem::size_of::<u32>() as u32) as _,
-                );
-                queue.submit(Some(encoder.finish()));
-
-                let buffer_slice = index_staging_buffer.slice(..);
-                let (sender, receiver) = futures_intrusive::channel::shared::oneshot_channel();
-                buffer_slice.map_async(wgpu::MapMode::Read, move |v| sender.send(v).unwrap());
-                device.poll(wgpu::Maintain::Wait);
-
-                let mut index_data: [u8; 24576] = [0; 24576];
-                if let Some(Ok(())) = receiver.receive().await {
-                    let data = buffer_slice.get_mapped_range();
-                    index_data.copy_from_slice(bytemuck::cast_slice(&data));
-                    drop(data);
-                    index_staging_buffer.unmap();
-                } else {
-                    panic!("failed to ingest index data!")
-                }
-                
-                new_chunks.insert(chunk_key.clone(), RawBufferData {
-                    vertex_data: vertex_data.clone(),
-                    index_data: index_data.clone(),
-                });
+        // generate everything missing in one go
+        if !missing.is_empty() {
+            let generated = self.generator.generate_meshes(&missing);
+            self.save_generated(&missing, &generated);
+            for (coord, chunk) in missing.into_iter().zip(generated) {
+                self.cache.insert(coord, chunk.clone());
+                new_chunks.insert(coord, chunk);
             }
         }
 
-      self.chunks = new_chunks;
+      new_chunks
+  }
+
+  fn load_saved(&mut self, coord: ChunkCoord) -> Option<RawChunkData> {
+      let regions = self.regions.as_mut()?;
+      regions.load(coord).unwrap_or_else(|e| {
+          log::warn!("failed to load chunk {:?}, regenerating it: {:#}", coord, e);
+          None
+      })
+  }
+
+  /// Replaces a chunk that was edited in game, so it is saved and later
+  /// loads get the edited terrain rather than generating it again.
+  pub fn store(&mut self, coord: ChunkCoord, chunk: RawChunkData) {
+      self.save(coord, &chunk);
+      self.cache.insert(coord, ChunkMeshes::Cpu(chunk));
+  }
+
+  /// Saves freshly generated chunks, reading the ones on the GPU back
+  /// together.
+  fn save_generated(&mut self, coords: &[ChunkCoord], chunks: &[ChunkMeshes]) {
+      if self.regions.is_none() {
+          return;
+      }
+      let mut on_gpu = Vec::new();
+      for (coord, chunk) in coords.iter().zip(chunks) {
+          match chunk {
+              ChunkMeshes::Cpu(raw) => self.save(*coord, raw),
+              ChunkMeshes::Gpu(data) => on_gpu.push((*coord, data.as_ref())),
+          }
+      }
+      if on_gpu.is_empty() {
+          return;
+      }
+      let data: Vec<&GpuChunkData> = on_gpu.iter().map(|(_, data)| *data).collect();
+      let read = pollster::block_on(read_gpu_chunks(&self.device, &self.queue, &data));
+      for ((coord, _), raw) in on_gpu.iter().zip(read) {
+          self.save(*coord, &raw);
+      }
+  }
+
+  /// Writes a chunk to the save directory, replacing any earlier save.
+  fn save(&mut self, coord: ChunkCoord, chunk: &RawChunkData) {
+      if let Some(regions) = self.regions.as_mut() {
+          if let Err(e) = regions.save(coord, chunk) {
+              log::warn!("failed to save chunk {:?}: {:#}", coord, e);
+          }
+      }
   }
 }
 
+/// Copies the given byte ranges out of GPU buffers through a single staging
+/// buffer and waits for them to land on the CPU.
+pub async fn read_buffers(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    buffers: &[(&wgpu::Buffer, Range<wgpu::BufferAddress>)],
+) -> Vec<Vec<u8>> {
+    let mut read = PendingRead::new(device, queue, buffers);
+    device.poll(wgpu::Maintain::Wait);
+    read.try_take().expect("failed to read back chunk data!")
+}
+
+/// Byte ranges of GPU buffers on their way to the CPU through a single
+/// staging buffer, like `read_buffers` but without waiting for them.
+pub struct PendingRead {
+    staging_buffer: wgpu::Buffer,
+    sizes: Vec<wgpu::BufferAddress>,
+    mapped: mpsc::Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+impl PendingRead {
+    /// Submits the copies and starts mapping them.
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        buffers: &[(&wgpu::Buffer, Range<wgpu::BufferAddress>)],
+    ) -> Self {
+        let sizes: Vec<_> = buffers.iter().map(|(_, range)| range.end - range.start).collect();
+        let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ComputeWorld: Staging"),
+            size: sizes.iter().sum(),
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ComputeWorld::read_buffers"),
+        });
+        let mut offset = 0;
+        for ((buffer, range), size) in buffers.iter().zip(&sizes) {
+            encoder.copy_buffer_to_buffer(buffer, range.start, &staging_buffer, offset, *size);
+            offset += size;
+        }
+        queue.submit(Some(encoder.finish()));
+
+        let (sender, mapped) = mpsc::channel();
+        staging_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| drop(sender.send(result)));
+        Self {
+            staging_buffer,
+            sizes,
+            mapped,
+        }
+    }
+
+    /// Bytes being read.
+    pub fn size_bytes(&self) -> usize {
+        self.staging_buffer.size() as usize
+    }
+
+    /// The copies, in the order they were asked for, once they have landed.
+    /// They only land when the device is polled. Returns `None` until then,
+    /// and after they have been taken.
+    pub fn try_take(&mut self) -> Option<Vec<Vec<u8>>> {
+        match self.mapped.try_recv() {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => panic!("failed to read back chunk data: {}", e),
+            Err(_) => return None,
+        }
+        let data = self.staging_buffer.slice(..).get_mapped_range();
+        let mut offset = 0;
+        let copies = self
+            .sizes
+            .iter()
+            .map(|size| {
+                let start = offset as usize;
+                offset += size;
+                data[start..offset as usize].to_vec()
+            })
+            .collect();
+        drop(data);
+        self.staging_buffer.unmap();
+        Some(copies)
+    }
+}
+
+/// Reads chunks left on the GPU back into CPU copies, all in one map.
+pub async fn read_gpu_chunks(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    chunks: &[&GpuChunkData],
+) -> Vec<RawChunkData> {
+    let mut ranges = Vec::new();
+    for chunk in chunks {
+        let (mut vertex_start, mut index_start) = (0, 0);
+        for lod in &chunk.lods {
+            let vertex_end = vertex_start + (lod.num_vertices * ChunkDimensions::VERTEX_SIZE) as u64;
+            let index_end = index_start + (lod.num_indices * ChunkDimensions::INDEX_SIZE) as u64;
+            ranges.push((&chunk.vertex_buffer, vertex_start..vertex_end));
+            ranges.push((&chunk.index_buffer, index_start..index_end));
+            (vertex_start, index_start) = (vertex_end, index_end);
+        }
+    }
+
+    let mut data = read_buffers(device, queue, &ranges).await.into_iter();
+    chunks
+        .iter()
+        .map(|chunk| {
+            let lods = chunk
+                .lods
+                .iter()
+                .map(|_| RawBufferData {
+                    vertex_data: data.next().unwrap(),
+                    index_data: data.next().unwrap(),
+                })
+                .collect();
+            RawChunkData::new(lods)
+        })
+        .collect()
+}
+
 pub struct ComputeWorldPipeline {
-  chunk_size: cgmath::Vector2<u32>,
-  min_max_height: cgmath::Vector2<f32>,
+  chunk_dimensions: ChunkDimensions,
+  lod_count: u32,
+  seed: u32,
+  max_batch_size: usize,
   gen_layout: wgpu::BindGroupLayout,
   gen_pipeline: wgpu::ComputePipeline,
+  erosion: Option<ErosionPipeline>,
+  bounds: MeshBoundsPipeline,
+}
+
+/// Every level of detail of a batch of chunks, recorded into `encoder` but
+/// not yet submitted.
+struct Batch {
+    jobs: Vec<ChunkData>,
+    jobs_buffer: wgpu::Buffer,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    encoder: wgpu::CommandEncoder,
 }
 
 impl ComputeWorldPipeline {
   pub fn new(
       device: &wgpu::Device,
+      options: &Options,
   ) -> Self {
       let gen_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
           label: Some("ChunkLoader::Layout"),
@@ -155,7 +455,7 @@ impl ComputeWorldPipeline {
                   binding: 0,
                   visibility: wgpu::ShaderStages::COMPUTE,
                   ty: wgpu::BindingType::Buffer {
-                      ty: wgpu::BufferBindingType::Uniform,
+                      ty: wgpu::BufferBindingType::Storage { read_only: true },
                       has_dynamic_offset: false,
                       min_binding_size: None,
                   },
@@ -198,102 +498,332 @@ impl ComputeWorldPipeline {
           entry_point: "gen_terrain_compute",
       });
 
+      // a batch has to fit in one storage binding, and its jobs in one
+      // dispatch dimension
+      let lod_count = options.lod_count();
+      let limits = device.limits();
+      let (vertex_bytes, index_bytes) = (0..lod_count)
+          .map(|level| options.chunk_dimensions.lod(level))
+          .fold((0, 0), |(vertices, indices), dimensions| {
+              (
+                  vertices + dimensions.vertex_buffer_size(),
+                  indices + dimensions.index_buffer_size(),
+              )
+          });
+      let max_binding_size = limits.max_storage_buffer_binding_size as u64;
+      let erosion = options
+          .erosion
+          .map(|erosion| ErosionPipeline::new(device, &shader, options, erosion));
+      let chunk_bytes = vertex_bytes
+          .max(index_bytes)
+          .max(erosion.as_ref().map_or(0, ErosionPipeline::chunk_bytes));
+      let max_batch_size = (max_binding_size / chunk_bytes)
+          .min((limits.max_compute_workgroups_per_dimension / lod_count) as u64)
+          .max(1) as usize;
+
       Self {
-          chunk_size: (32, 32).into(),
-          min_max_height: (-5.0, 5.0).into(),
+          chunk_dimensions: options.chunk_dimensions,
+          lod_count,
+          seed: options.seed,
+          max_batch_size,
           gen_layout,
           gen_pipeline,
+          erosion,
+          bounds: MeshBoundsPipeline::new(device, &shader),
       }
   }
   
-    pub fn gen_chunk(
+    /// Most chunks `gen_chunks` can generate in one call on this device.
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    fn chunk_data(
         &self,
-        device: &wgpu::Device,
-        queue: &wgpu::Queue,
-        corner: cgmath::Vector2<i32>,
-    ) -> Chunk {
-        let chunk_name = format!("Chunk {:?}", corner);
-        let num_vertices = (self.chunk_size.x + 1) * (self.chunk_size.y + 1);
-        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some(&format!("{}: Vertices", chunk_name)),
-            size: (num_vertices * 8 * std::mem::size_of::<f32>() as u32) as _,
-            usage: wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::VERTEX
-                | wgpu::BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-        let num_elements = self.chunk_size.x * self.chunk_size.y * 6;
-        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some(&format!("{}: Indices", chunk_name)),
-            size: (num_elements * std::mem::size_of::<u32>() as u32) as _,
-            usage: wgpu::BufferUsages::STORAGE
-                | wgpu::BufferUsages::INDEX
-                | wgpu::BufferUsages::COPY_SRC,
-            mapped_at_creation: false,
-        });
-        let chunk = Chunk {
-            mesh: model::Mesh {
-                name: chunk_name,
-                vertex_buffer,
-                index_buffer,
-                num_elements,
-                material: 0,
-                index_format: wgpu::IndexFormat::Uint32,
-            },
-        };
-
-        let data = ChunkData {
-            chunk_size: self.chunk_size.into(),
+        corner: cgmath::Vector2<f32>,
+        dimensions: &ChunkDimensions,
+        vertex_offset: u32,
+        index_offset: u32,
+    ) -> ChunkData {
+        ChunkData {
+            chunk_size: dimensions.resolution.into(),
             chunk_corner: corner.into(),
-            min_max_height: self.min_max_height.into(),
-        };
-        let gen_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            chunk_extent: dimensions.extent.into(),
+            skirt_depth: dimensions.skirt_depth(),
+            vertex_offset,
+            index_offset,
+            seed: self.seed,
+        }
+    }
+
+    fn jobs_buffer(device: &wgpu::Device, jobs: &[ChunkData]) -> wgpu::Buffer {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("TerrainPipeline: ChunkData"),
-            size: size_of_val(&data) as _,
-            usage: wgpu::BufferUsages::UNIFORM
-                | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-        queue.write_buffer(&gen_buffer, 0, bytemuck::bytes_of(&data));
+            contents: bytemuck::cast_slice(jobs),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    }
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+    fn gen_bind_group(
+        &self,
+        device: &wgpu::Device,
+        jobs_buffer: &wgpu::Buffer,
+        vertex_buffer: &wgpu::Buffer,
+        index_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
             label: Some("TerrainPipeline: BindGroup"),
             layout: &self.gen_layout,
             entries: &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: gen_buffer.as_entire_binding(),
+                    resource: jobs_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
-                    resource: chunk.mesh.vertex_buffer.as_entire_binding(),
+                    resource: vertex_buffer.as_entire_binding(),
                 },
                 wgpu::BindGroupEntry {
                     binding: 2,
-                    resource: chunk.mesh.index_buffer.as_entire_binding(),
+                    resource: index_buffer.as_entire_binding(),
                 },
             ],
+        })
+    }
+
+    /// Records generating every level of detail of each chunk in a single
+    /// compute pass, then eroding them when erosion is on.
+    ///
+    /// All meshes share one vertex and one index buffer; every (chunk, LOD)
+    /// pair is a job picked by the dispatch's y workgroup, and the levels of
+    /// each chunk follow one another.
+    fn encode_batch(&self, device: &wgpu::Device, coords: &[ChunkCoord]) -> Batch {
+        assert!(coords.len() <= self.max_batch_size, "chunk batch too large");
+
+        let mut jobs = Vec::new();
+        let mut vertex_count = 0;
+        let mut index_count = 0;
+        for coord in coords {
+            let corner = coord.corner(&self.chunk_dimensions);
+            for level in 0..self.lod_count {
+                let dimensions = self.chunk_dimensions.lod(level);
+                jobs.push(self.chunk_data(corner, &dimensions, vertex_count, index_count));
+                vertex_count += dimensions.num_vertices();
+                index_count += dimensions.num_indices();
+            }
+        }
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TerrainPipeline: Batch Vertices"),
+            size: (vertex_count * ChunkDimensions::VERTEX_SIZE) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let index_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TerrainPipeline: Batch Indices"),
+            size: (index_count * ChunkDimensions::INDEX_SIZE) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
         });
+        let jobs_buffer = Self::jobs_buffer(device, &jobs);
+        let bind_group = self.gen_bind_group(device, &jobs_buffer, &vertex_buffer, &index_buffer);
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("TerrainPipeline::gen_chunk"),
+            label: Some("TerrainPipeline::gen_chunks"),
         });
-
         let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
             label: Some("TerrainPipeline: ComputePass"),
         });
         cpass.set_pipeline(&self.gen_pipeline);
         cpass.set_bind_group(0, &bind_group, &[]);
-        cpass.dispatch_workgroups(
-            (((self.chunk_size.x + 1) * (self.chunk_size.y + 1)) as f32 / 64.0).ceil() as _,
-            1,
-            1,
-        );
+        // the most detailed level has the most vertices, the rest exit early
+        let num_vertices = self.chunk_dimensions.num_vertices();
+        cpass.dispatch_workgroups((num_vertices as f32 / 64.0).ceil() as _, jobs.len() as _, 1);
         drop(cpass);
+        if let Some(erosion) = &self.erosion {
+            erosion.encode(device, &mut encoder, &jobs_buffer, &vertex_buffer, coords.len() as u32);
+        }
+
+        Batch {
+            jobs,
+            jobs_buffer,
+            vertex_buffer,
+            index_buffer,
+            encoder,
+        }
+    }
+
+    /// Generates a batch of chunks and reads them all back with one map. At
+    /// most `max_batch_size` chunks can be generated at once.
+    pub async fn gen_chunks(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        coords: &[ChunkCoord],
+    ) -> Vec<RawChunkData> {
+        let batch = self.encode_batch(device, coords);
+        queue.submit(std::iter::once(batch.encoder.finish()));
+
+        let mut data = read_buffers(
+            device,
+            queue,
+            &[
+                (&batch.vertex_buffer, 0..batch.vertex_buffer.size()),
+                (&batch.index_buffer, 0..batch.index_buffer.size()),
+            ],
+        )
+        .await
+        .into_iter();
+        let (vertex_data, index_data) = (data.next().unwrap(), data.next().unwrap());
+
+        // split the shared buffers back up into chunks
+        let mut jobs = batch.jobs.iter();
+        coords
+            .iter()
+            .map(|_| {
+                let lods = (0..self.lod_count)
+                    .map(|level| {
+                        let job = jobs.next().unwrap();
+                        let dimensions = self.chunk_dimensions.lod(level);
+                        let vertex_start = (job.vertex_offset * ChunkDimensions::VERTEX_SIZE) as usize;
+                        let index_start = (job.index_offset * ChunkDimensions::INDEX_SIZE) as usize;
+                        RawBufferData {
+                            vertex_data: vertex_data
+                                [vertex_start..vertex_start + dimensions.vertex_buffer_size() as usize]
+                                .to_vec(),
+                            index_data: index_data
+                                [index_start..index_start + dimensions.index_buffer_size() as usize]
+                                .to_vec(),
+                        }
+                    })
+                    .collect();
+                RawChunkData::new(lods)
+            })
+            .collect()
+    }
+
+    /// Generates a batch of chunks like `gen_chunks`, but leaves the meshes
+    /// on the GPU. Only their height ranges are read back.
+    pub async fn gen_gpu_chunks(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        coords: &[ChunkCoord],
+    ) -> Vec<GpuChunkData> {
+        let mut batch = self.encode_batch(device, coords);
+        let bounds = self.bounds.encode(
+            device,
+            &mut batch.encoder,
+            &batch.jobs_buffer,
+            &batch.vertex_buffer,
+            batch.jobs.len() as u32,
+            self.chunk_dimensions.num_vertices(),
+        );
+
+        let lods: Vec<MeshSize> = (0..self.lod_count)
+            .map(|level| {
+                let dimensions = self.chunk_dimensions.lod(level);
+                MeshSize {
+                    num_vertices: dimensions.num_vertices(),
+                    num_indices: dimensions.num_indices(),
+                }
+            })
+            .collect();
+        let vertex_size = lods.iter().map(|lod| lod.num_vertices).sum::<u32>() * ChunkDimensions::VERTEX_SIZE;
+        let index_size = lods.iter().map(|lod| lod.num_indices).sum::<u32>() * ChunkDimensions::INDEX_SIZE;
+
+        // copied into buffers of their own, so nothing holds on to the batch
+        let buffers: Vec<(wgpu::Buffer, wgpu::Buffer)> = batch
+            .jobs
+            .chunks(self.lod_count as usize)
+            .map(|jobs| {
+                let chunk_buffer = |label, size| {
+                    device.create_buffer(&wgpu::BufferDescriptor {
+                        label: Some(label),
+                        size: size as u64,
+                        usage: wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    })
+                };
+                let vertex_buffer = chunk_buffer("TerrainPipeline: Chunk Vertices", vertex_size);
+                let index_buffer = chunk_buffer("TerrainPipeline: Chunk Indices", index_size);
+                batch.encoder.copy_buffer_to_buffer(
+                    &batch.vertex_buffer,
+                    (jobs[0].vertex_offset * ChunkDimensions::VERTEX_SIZE) as u64,
+                    &vertex_buffer,
+                    0,
+                    vertex_size as u64,
+                );
+                batch.encoder.copy_buffer_to_buffer(
+                    &batch.index_buffer,
+                    (jobs[0].index_offset * ChunkDimensions::INDEX_SIZE) as u64,
+                    &index_buffer,
+                    0,
+                    index_size as u64,
+                );
+                (vertex_buffer, index_buffer)
+            })
+            .collect();
+        queue.submit(std::iter::once(batch.encoder.finish()));
+
+        let bounds = MeshBoundsPipeline::read(device, queue, &bounds).await;
+        buffers
+            .into_iter()
+            .zip(bounds.chunks(self.lod_count as usize))
+            .map(|((vertex_buffer, index_buffer), bounds)| GpuChunkData {
+                vertex_buffer,
+                index_buffer,
+                lods: lods.clone(),
+                height_range: bounds
+                    .iter()
+                    .fold((f32::MAX, f32::MIN), |(min, max), (low, high)| (min.min(*low), max.max(*high))),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
 
-        queue.submit(std::iter::once(encoder.finish()));
-        device.poll(wgpu::Maintain::Wait);
+    use super::*;
+    use crate::testing::gpu_test;
+    use crate::world::new_generator;
 
-        chunk
+    gpu_test! {
+        fn saves_chunks_left_on_the_gpu(device, queue) {
+            // the default options, saving to a directory of the test's own
+            let dir = std::env::temp_dir().join(format!("rust_game-compute-world-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            let options = Options {
+                save_dir: Some(dir.clone()),
+                ..Default::default()
+            };
+            let (device, queue) = (Arc::new(device), Arc::new(queue));
+            let compute_world = || {
+                let generator = new_generator(&options, Arc::clone(&device), Arc::clone(&queue)).unwrap();
+                ComputeWorld::new(&options, generator, Arc::clone(&device), Arc::clone(&queue))
+            };
+            let coords = [ChunkCoord::new(0, 0), ChunkCoord::new(2, -1)];
+            let generated = compute_world().load_chunks(&coords);
+            // a new world finds the saves rather than generating them again
+            let loaded = compute_world().load_chunks(&coords);
+
+            for coord in coords {
+                let ChunkMeshes::Gpu(data) = &generated[&coord] else {
+                    panic!("chunk {:?} was handed on from the CPU", coord);
+                };
+                let ChunkMeshes::Cpu(saved) = &loaded[&coord] else {
+                    panic!("chunk {:?} wasn't saved", coord);
+                };
+                let read = pollster::block_on(read_gpu_chunks(&device, &queue, &[data.as_ref()])).remove(0);
+                assert_eq!(saved.lods.len(), read.lods.len());
+                for (saved, read) in saved.lods.iter().zip(&read.lods) {
+                    assert_eq!(saved.vertex_data, read.vertex_data);
+                    assert_eq!(saved.index_data, read.index_data);
+                }
+            }
+            fs::remove_dir_all(&dir).unwrap();
+        }
     }
-}
\ No newline at end of file
+}
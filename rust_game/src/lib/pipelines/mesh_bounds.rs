@@ -0,0 +1,115 @@
+use wgpu::util::DeviceExt;
+
+use super::load_chunks::read_buffers;
+
+/// The `mesh_bounds_compute` pass of `gen_terrain.wgsl`, measuring the
+/// height range of every mesh in a batch on the GPU so only the ranges have
+/// to be read back.
+pub struct MeshBoundsPipeline {
+    layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl MeshBoundsPipeline {
+    pub fn new(device: &wgpu::Device, shader: &wgpu::ShaderModule) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        // same slots as the generation layout, with the bounds in place of
+        // the indices
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("MeshBoundsPipeline::Layout"),
+            entries: &[storage(0, true), storage(1, false), storage(10, false)],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("MeshBoundsPipeline::PipelineLayout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("mesh_bounds_compute"),
+            layout: Some(&pipeline_layout),
+            module: shader,
+            entry_point: "mesh_bounds_compute",
+        });
+
+        Self { layout, pipeline }
+    }
+
+    /// Records the pass over `job_count` meshes of at most `max_vertices`
+    /// vertices that `gen_terrain_compute` has written to `vertices` from
+    /// `jobs`. Returns the buffer the bounds end up in, for `read`.
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        jobs: &wgpu::Buffer,
+        vertices: &wgpu::Buffer,
+        job_count: u32,
+        max_vertices: u32,
+    ) -> wgpu::Buffer {
+        // lowest and highest per mesh, starting from either end
+        let initial: Vec<u32> = (0..job_count).flat_map(|_| [u32::MAX, 0]).collect();
+        let bounds = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("MeshBoundsPipeline: Bounds"),
+            contents: bytemuck::cast_slice(&initial),
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("MeshBoundsPipeline: BindGroup"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: jobs.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 10,
+                    resource: bounds.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("MeshBoundsPipeline: ComputePass"),
+        });
+        cpass.set_pipeline(&self.pipeline);
+        cpass.set_bind_group(0, &bind_group, &[]);
+        cpass.dispatch_workgroups((max_vertices as f32 / 64.0).ceil() as u32, job_count, 1);
+        bounds
+    }
+
+    /// Lowest and highest vertex of each mesh, once the pass has been
+    /// submitted.
+    pub async fn read(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bounds: &wgpu::Buffer,
+    ) -> Vec<(f32, f32)> {
+        let data = read_buffers(device, queue, &[(bounds, 0..bounds.size())]).await;
+        bytemuck::pod_collect_to_vec::<u8, [u32; 2]>(&data[0])
+            .into_iter()
+            .map(|[low, high]| (from_ordered_bits(low), from_ordered_bits(high)))
+            .collect()
+    }
+}
+
+/// Undoes `ordered_bits` in `gen_terrain.wgsl`.
+fn from_ordered_bits(bits: u32) -> f32 {
+    if bits & 0x8000_0000 != 0 {
+        f32::from_bits(bits & !0x8000_0000)
+    } else {
+        f32::from_bits(!bits)
+    }
+}
@@ -1,2 +1,7 @@
-pub mod ray_intersection;
-pub mod load_chunks;
\ No newline at end of file
+pub mod chunk_cache;
+pub mod chunk_jobs;
+pub mod erosion;
+pub mod mesh_bounds;
+pub mod raycast;
+pub mod voxel;
+pub mod load_chunks;
@@ -0,0 +1,188 @@
+use wgpu::util::DeviceExt;
+
+use super::load_chunks::read_buffers;
+
+/// The part of a ray crossing one chunk, a `Segment` in `raycast.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct RaySegment {
+    pub origin: [f32; 3],
+    pub max_distance: f32,
+    /// Normalized.
+    pub direction: [f32; 3],
+    /// Index of the ray in the batch.
+    pub ray: u32,
+    /// Where the chunk's most detailed mesh sits in the `ChunkArena`.
+    pub base_vertex: u32,
+    pub first_index: u32,
+    /// Indices of the triangles to test, leaving out any skirts.
+    pub num_indices: u32,
+    pub _padding: u32,
+}
+
+/// A `Hit` in `raycast.wgsl`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct GpuHit {
+    pub position: [f32; 3],
+    pub distance: f32,
+    pub normal: [f32; 3],
+    /// Index of the segment hit, or `GpuHit::MISS`.
+    pub segment: u32,
+    /// Triangle hit within the segment's mesh. Of triangles hit at the same
+    /// distance, the one in the first segment the ray crosses, and the
+    /// lowest there.
+    pub triangle: u32,
+    pub _padding: [u32; 3],
+}
+
+impl GpuHit {
+    pub const MISS: u32 = u32::MAX;
+}
+
+/// Casts batches of rays against the meshes in the `ChunkArena`, finding
+/// the nearest hit of each ray without reading any chunk back.
+pub struct RaycastPipeline {
+    layout: wgpu::BindGroupLayout,
+    nearest_pipeline: wgpu::ComputePipeline,
+    segment_pipeline: wgpu::ComputePipeline,
+    triangle_pipeline: wgpu::ComputePipeline,
+    resolve_pipeline: wgpu::ComputePipeline,
+    /// Most workgroups along one dimension of a dispatch.
+    max_workgroups: u32,
+}
+
+impl RaycastPipeline {
+    pub fn new(device: &wgpu::Device) -> Self {
+        let shader = device.create_shader_module(wgpu::include_wgsl!("raycast.wgsl"));
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("RaycastPipeline::Layout"),
+            entries: &[storage(0, true), storage(1, true), storage(2, true), storage(3, false)],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("RaycastPipeline::PipelineLayout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        Self {
+            nearest_pipeline: pipeline("raycast_nearest"),
+            segment_pipeline: pipeline("raycast_segment"),
+            triangle_pipeline: pipeline("raycast_triangle"),
+            resolve_pipeline: pipeline("raycast_resolve"),
+            layout,
+            max_workgroups: device.limits().max_compute_workgroups_per_dimension,
+        }
+    }
+
+    /// Records the passes over the `segments` of `ray_count` rays, given in
+    /// the order each ray crosses them, testing the triangles the segments
+    /// pick out of the arena's `vertices` and `indices`. Returns the buffer
+    /// the hits end up in, one per ray, for `read`.
+    pub fn encode(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        ray_count: usize,
+        segments: &[RaySegment],
+        vertices: &wgpu::Buffer,
+        indices: &wgpu::Buffer,
+    ) -> wgpu::Buffer {
+        let storage = |label, contents: &[u8], usage| {
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE | usage,
+            })
+        };
+        // distances start past any hit, as the largest bits there are
+        let misses = vec![
+            GpuHit {
+                position: [0.0; 3],
+                distance: f32::from_bits(u32::MAX),
+                normal: [0.0; 3],
+                segment: GpuHit::MISS,
+                triangle: u32::MAX,
+                _padding: [0; 3],
+            };
+            ray_count
+        ];
+        let hits = storage("RaycastPipeline: Hits", bytemuck::cast_slice(&misses), wgpu::BufferUsages::COPY_SRC);
+        // nothing to bind when no ray crosses a loaded chunk
+        if segments.is_empty() {
+            return hits;
+        }
+        let segments_buffer = storage(
+            "RaycastPipeline: Segments",
+            bytemuck::cast_slice(segments),
+            wgpu::BufferUsages::empty(),
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("RaycastPipeline: BindGroup"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: segments_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: vertices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: indices.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: hits.as_entire_binding(),
+                },
+            ],
+        });
+
+        // a workgroup per segment, wrapping onto y past the dispatch limit
+        let count = segments.len() as u32;
+        let groups = (count.min(self.max_workgroups), count.div_ceil(self.max_workgroups));
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("RaycastPipeline: ComputePass"),
+        });
+        cpass.set_bind_group(0, &bind_group, &[]);
+        let passes = [
+            &self.nearest_pipeline,
+            &self.segment_pipeline,
+            &self.triangle_pipeline,
+            &self.resolve_pipeline,
+        ];
+        for pipeline in passes {
+            cpass.set_pipeline(pipeline);
+            cpass.dispatch_workgroups(groups.0, groups.1, 1);
+        }
+        hits
+    }
+
+    /// Nearest hit of each ray, once the passes have been submitted.
+    pub async fn read(device: &wgpu::Device, queue: &wgpu::Queue, hits: &wgpu::Buffer) -> Vec<GpuHit> {
+        let data = read_buffers(device, queue, &[(hits, 0..hits.size())]).await;
+        bytemuck::pod_collect_to_vec(&data[0])
+    }
+}
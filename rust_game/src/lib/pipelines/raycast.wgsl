@@ -0,0 +1,183 @@
+// Casts a batch of rays against the chunks in the `ChunkArena`. Every ray
+// is split into segments, one per loaded chunk it crosses, and each
+// workgroup tests one segment against the triangles of that chunk.
+//
+// The nearest hit of each ray is found in passes: `raycast_nearest` keeps
+// the shortest distance any triangle gives, `raycast_segment` and
+// `raycast_triangle` pick the first segment and the lowest triangle in it
+// of those hit at that distance, then `raycast_resolve` has that one
+// triangle write out the hit. Distances are never negative, so their bits
+// sort the same way the floats do, and a ray's segments are in the order
+// it crosses them.
+
+struct Segment {
+    // the ray, repeated for each of its segments
+    origin: vec3<f32>,
+    max_distance: f32,
+    // normalized
+    direction: vec3<f32>,
+    ray: u32,
+    // where the chunk's most detailed mesh sits in the arena
+    base_vertex: u32,
+    first_index: u32,
+    num_indices: u32,
+}
+
+struct Vertex {
+    position: vec3<f32>,
+    biome: u32,
+    normal: vec3<f32>,
+}
+
+struct Hit {
+    position: vec3<f32>,
+    // bits of the shortest distance so far
+    distance: atomic<u32>,
+    normal: vec3<f32>,
+    // index into `segments`, or 0xffffffff for a miss
+    segment: atomic<u32>,
+    // index of the triangle hit within the segment's mesh
+    triangle: atomic<u32>,
+}
+
+@group(0) @binding(0) var<storage, read> segments: array<Segment>;
+@group(0) @binding(1) var<storage, read> vertices: array<Vertex>; // stride: 32
+@group(0) @binding(2) var<storage, read> indices: array<u32>;
+@group(0) @binding(3) var<storage, read_write> hits: array<Hit>;
+
+const MISS: f32 = -1.0;
+
+struct Intersection {
+    distance: f32,
+    // weights of the second and third corner
+    uv: vec2<f32>,
+}
+
+// Möller–Trumbore, from either side of the triangle.
+fn intersect(ray: Segment, v0: vec3<f32>, v1: vec3<f32>, v2: vec3<f32>) -> Intersection {
+    let miss = Intersection(MISS, vec2<f32>(0.0));
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let h = cross(ray.direction, edge2);
+    let a = dot(edge1, h);
+    if (abs(a) < 1e-8) {
+        return miss; // parallel to the triangle
+    }
+
+    let f = 1.0 / a;
+    let s = ray.origin - v0;
+    let u = f * dot(s, h);
+    if (u < 0.0 || u > 1.0) {
+        return miss;
+    }
+    let q = cross(s, edge1);
+    let v = f * dot(ray.direction, q);
+    if (v < 0.0 || u + v > 1.0) {
+        return miss;
+    }
+    return Intersection(f * dot(edge2, q), vec2<f32>(u, v));
+}
+
+// Corners of a triangle of a segment's chunk.
+fn corner(segment: Segment, triangle: u32, i: u32) -> Vertex {
+    return vertices[segment.base_vertex + indices[segment.first_index + triangle * 3u + i]];
+}
+
+// Hit of a segment's ray on one of its chunk's triangles, within reach.
+fn intersect_triangle(segment: Segment, triangle: u32) -> Intersection {
+    let hit = intersect(
+        segment,
+        corner(segment, triangle, 0u).position,
+        corner(segment, triangle, 1u).position,
+        corner(segment, triangle, 2u).position,
+    );
+    if (hit.distance < 0.0 || hit.distance > segment.max_distance) {
+        return Intersection(MISS, hit.uv);
+    }
+    return hit;
+}
+
+// Segments take two dimensions of workgroups once there are too many for one.
+fn segment_index(group: vec3<u32>, groups: vec3<u32>) -> u32 {
+    return group.y * groups.x + group.x;
+}
+
+@compute @workgroup_size(64)
+fn raycast_nearest(
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let index = segment_index(group, groups);
+    if (index >= arrayLength(&segments)) { return; }
+    let segment = segments[index];
+
+    for (var triangle = local; triangle < segment.num_indices / 3u; triangle += 64u) {
+        let hit = intersect_triangle(segment, triangle);
+        if (hit.distance != MISS) {
+            atomicMin(&hits[segment.ray].distance, bitcast<u32>(hit.distance));
+        }
+    }
+}
+
+// Whether a triangle of a segment is hit at the ray's shortest distance.
+fn is_nearest(segment: Segment, triangle: u32) -> bool {
+    let hit = intersect_triangle(segment, triangle);
+    return hit.distance != MISS && bitcast<u32>(hit.distance) == atomicLoad(&hits[segment.ray].distance);
+}
+
+@compute @workgroup_size(64)
+fn raycast_segment(
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let index = segment_index(group, groups);
+    if (index >= arrayLength(&segments)) { return; }
+    let segment = segments[index];
+
+    for (var triangle = local; triangle < segment.num_indices / 3u; triangle += 64u) {
+        if (is_nearest(segment, triangle)) {
+            atomicMin(&hits[segment.ray].segment, index);
+        }
+    }
+}
+
+@compute @workgroup_size(64)
+fn raycast_triangle(
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+    @builtin(local_invocation_index) local: u32,
+) {
+    let index = segment_index(group, groups);
+    if (index >= arrayLength(&segments)) { return; }
+    let segment = segments[index];
+    if (atomicLoad(&hits[segment.ray].segment) != index) { return; }
+
+    for (var triangle = local; triangle < segment.num_indices / 3u; triangle += 64u) {
+        if (is_nearest(segment, triangle)) {
+            atomicMin(&hits[segment.ray].triangle, triangle);
+        }
+    }
+}
+
+@compute @workgroup_size(1)
+fn raycast_resolve(
+    @builtin(workgroup_id) group: vec3<u32>,
+    @builtin(num_workgroups) groups: vec3<u32>,
+) {
+    let index = segment_index(group, groups);
+    if (index >= arrayLength(&segments)) { return; }
+    let segment = segments[index];
+    if (atomicLoad(&hits[segment.ray].segment) != index) { return; }
+
+    let triangle = atomicLoad(&hits[segment.ray].triangle);
+    let hit = intersect_triangle(segment, triangle);
+    // normals blended the way the terrain is shaded
+    let w = vec3<f32>(1.0 - hit.uv.x - hit.uv.y, hit.uv);
+    let normal = corner(segment, triangle, 0u).normal * w.x
+        + corner(segment, triangle, 1u).normal * w.y
+        + corner(segment, triangle, 2u).normal * w.z;
+    hits[segment.ray].position = segment.origin + segment.direction * hit.distance;
+    hits[segment.ray].normal = normalize(normal);
+}
@@ -0,0 +1,397 @@
+use wgpu::util::DeviceExt;
+
+use crate::lib::options::Options;
+use crate::world::marching_cubes;
+use crate::world::{ChunkCoord, ChunkDimensions};
+
+use super::load_chunks::{read_buffers, RawBufferData, RawChunkData};
+
+/// Lowest and highest ground the density field is sampled between. Nothing
+/// is generated outside: caves stop short of the floor, and `gen_terrain.wgsl`
+/// flattens anything reaching for the ceiling off just below it.
+const VOXEL_FLOOR: f32 = -48.0;
+const VOXEL_CEILING: f32 = 64.0;
+/// Vertices each column of cells has room for on average to begin with.
+/// The ground takes about six, caves and overhangs cross it again and again;
+/// meshes needing more are made again with the room they need.
+const VERTICES_PER_COLUMN: u32 = 32;
+/// Bytes of a `VoxelCount` in `gen_terrain.wgsl`.
+const COUNT_SIZE: u64 = 16;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct VoxelJob {
+    size: [u32; 3],
+    seed: u32,
+    origin: [f32; 3],
+    /// Where this mesh starts in the shared vertex buffer.
+    vertex_offset: u32,
+    spacing: [f32; 3],
+    vertex_capacity: u32,
+    density_offset: u32,
+    _padding: [u32; 3],
+}
+
+impl VoxelJob {
+    fn points(&self) -> u32 {
+        self.size.iter().map(|size| size + 1).product()
+    }
+
+    fn cells(&self) -> u32 {
+        self.size.iter().product()
+    }
+}
+
+/// The voxel passes of `gen_terrain.wgsl`: samples a density field over
+/// each chunk and polygonises it with marching cubes. The meshes are triangle
+/// lists in the vertex layout of the heightfield, indexed in order, with as
+/// many vertices as the surface needs.
+///
+/// Only the most detailed level is made, with cells as tall as they are
+/// wide. Voxel meshes have no skirts to hide the cracks where chunks of
+/// different levels meet, so every chunk is drawn at full detail.
+pub struct VoxelPipeline {
+    chunk_dimensions: ChunkDimensions,
+    lod_count: u32,
+    seed: u32,
+    vertices_per_column: u32, // room each mesh starts with, see `VERTICES_PER_COLUMN`
+    max_batch_size: usize,
+    table_buffer: wgpu::Buffer,
+    layout: wgpu::BindGroupLayout,
+    density_pipeline: wgpu::ComputePipeline,
+    polygonise_pipeline: wgpu::ComputePipeline,
+}
+
+impl VoxelPipeline {
+    pub fn new(device: &wgpu::Device, options: &Options) -> Self {
+        let table_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("VoxelPipeline: Triangle Table"),
+            contents: bytemuck::cast_slice(&marching_cubes::packed_table()),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("VoxelPipeline::Layout"),
+            entries: &[
+                storage(1, false),
+                storage(6, true),
+                storage(7, false),
+                storage(8, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 9,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let shader = device.create_shader_module(wgpu::include_wgsl!("gen_terrain.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("VoxelPipeline::PipelineLayout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let compute_pipeline = |entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        let mut pipeline = Self {
+            chunk_dimensions: options.chunk_dimensions,
+            lod_count: 1,
+            seed: options.seed,
+            vertices_per_column: VERTICES_PER_COLUMN,
+            max_batch_size: 1,
+            table_buffer,
+            layout,
+            density_pipeline: compute_pipeline("voxel_density_compute"),
+            polygonise_pipeline: compute_pipeline("voxel_polygonise_compute"),
+        };
+
+        // a batch has to fit in one storage binding, and its jobs in one
+        // dispatch dimension
+        let limits = device.limits();
+        let jobs = pipeline.chunk_jobs(ChunkCoord::new(0, 0), &mut 0, &mut 0);
+        let vertex_bytes: u64 = jobs
+            .iter()
+            .map(|job| (job.vertex_capacity * ChunkDimensions::VERTEX_SIZE) as u64)
+            .sum();
+        let density_bytes: u64 = jobs.iter().map(|job| job.points() as u64 * 4).sum();
+        pipeline.max_batch_size = (limits.max_storage_buffer_binding_size as u64
+            / vertex_bytes.max(density_bytes))
+            .min((limits.max_compute_workgroups_per_dimension / pipeline.lod_count) as u64)
+            .max(1) as usize;
+        pipeline
+    }
+
+    /// Most chunks `gen_chunks` can generate in one call on this device.
+    pub fn max_batch_size(&self) -> usize {
+        self.max_batch_size
+    }
+
+    /// A job for every level of detail of a chunk, placed after
+    /// `vertex_count` vertices and `point_count` density samples, which are
+    /// moved on past them.
+    fn chunk_jobs(&self, coord: ChunkCoord, vertex_count: &mut u32, point_count: &mut u32) -> Vec<VoxelJob> {
+        let corner = coord.corner(&self.chunk_dimensions);
+        (0..self.lod_count)
+            .map(|level| {
+                let dimensions = self.chunk_dimensions.lod(level);
+                let spacing = dimensions.spacing();
+                let height = ((VOXEL_CEILING - VOXEL_FLOOR) / spacing.x).ceil() as u32;
+                let job = VoxelJob {
+                    size: [dimensions.resolution.x, height, dimensions.resolution.y],
+                    seed: self.seed,
+                    origin: [corner.x, VOXEL_FLOOR, corner.y],
+                    vertex_offset: *vertex_count,
+                    spacing: [spacing.x, spacing.x, spacing.y],
+                    vertex_capacity: dimensions.resolution.x * dimensions.resolution.y * self.vertices_per_column,
+                    density_offset: *point_count,
+                    _padding: [0; 3],
+                };
+                *vertex_count += job.vertex_capacity;
+                *point_count += job.points();
+                job
+            })
+            .collect()
+    }
+
+    /// Generates every level of detail of each chunk, then reads back how
+    /// many vertices each mesh came to and only those vertices.
+    ///
+    /// Like `ComputeWorldPipeline::gen_chunks`, every (chunk, LOD) pair is a
+    /// job picked by the dispatch's y workgroup, and all meshes share one
+    /// vertex buffer, each with room for a fixed number of vertices. Meshes
+    /// that outgrow it are generated again in a second round, each with room
+    /// for the count the first round found. At most `max_batch_size` chunks
+    /// can be generated at once.
+    pub async fn gen_chunks(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        coords: &[ChunkCoord],
+    ) -> Vec<RawChunkData> {
+        assert!(coords.len() <= self.max_batch_size, "chunk batch too large");
+
+        let (mut vertex_count, mut point_count) = (0, 0);
+        let jobs: Vec<VoxelJob> = coords
+            .iter()
+            .flat_map(|coord| self.chunk_jobs(*coord, &mut vertex_count, &mut point_count))
+            .collect();
+        let (vertex_buffer, counts) = self.run_jobs(device, queue, &jobs, vertex_count, point_count).await;
+
+        // (round, job) each mesh ends up made by, with its vertex count
+        let mut meshes: Vec<(usize, VoxelJob, u32)> = jobs.iter().zip(&counts).map(|(job, count)| (0, *job, *count)).collect();
+        let (mut vertex_count, mut point_count) = (0, 0);
+        let mut regrown = Vec::new();
+        for (round, job, count) in meshes.iter_mut() {
+            if *count <= job.vertex_capacity {
+                continue;
+            }
+            job.vertex_offset = vertex_count;
+            job.vertex_capacity = *count;
+            job.density_offset = point_count;
+            vertex_count += job.vertex_capacity;
+            point_count += job.points();
+            *round = 1;
+            regrown.push(*job);
+        }
+        let mut buffers = vec![vertex_buffer];
+        if !regrown.is_empty() {
+            log::debug!("{} voxel meshes outgrew their room, generating them again", regrown.len());
+            let (regrown_buffer, regrown_counts) =
+                self.run_jobs(device, queue, &regrown, vertex_count, point_count).await;
+            let regrown_meshes = meshes.iter_mut().filter(|(round, _, _)| *round == 1);
+            for ((_, _, count), regrown_count) in regrown_meshes.zip(regrown_counts) {
+                *count = regrown_count;
+            }
+            buffers.push(regrown_buffer);
+        }
+
+        // pack the vertices each mesh actually used next to each other, so
+        // only those are read back
+        let mut used = Vec::new();
+        let mut used_bytes = 0;
+        for (_, job, count) in &meshes {
+            // whole triangles only, in case a mesh still didn't fit
+            let vertices = (*count).min(job.vertex_capacity / 3 * 3);
+            let size = (vertices * ChunkDimensions::VERTEX_SIZE) as u64;
+            used.push((vertices, used_bytes, size));
+            used_bytes += size;
+        }
+
+        let mut vertex_data = Vec::new();
+        if used_bytes > 0 {
+            let packed = device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("VoxelPipeline: Used Vertices"),
+                size: used_bytes,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false,
+            });
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("VoxelPipeline::gen_chunks::pack"),
+            });
+            for ((round, job, _), (_, offset, size)) in meshes.iter().zip(&used) {
+                if *size > 0 {
+                    let start = (job.vertex_offset * ChunkDimensions::VERTEX_SIZE) as u64;
+                    encoder.copy_buffer_to_buffer(&buffers[*round], start, &packed, *offset, *size);
+                }
+            }
+            queue.submit(std::iter::once(encoder.finish()));
+            vertex_data = read_buffers(device, queue, &[(&packed, 0..used_bytes)]).await.remove(0);
+        }
+
+        // every triangle has its own vertices, so the indices just count up
+        let mut used = used.into_iter();
+        coords
+            .iter()
+            .map(|_| {
+                let lods = (0..self.lod_count)
+                    .map(|_| {
+                        let (vertices, offset, size) = used.next().unwrap();
+                        let indices: Vec<u32> = (0..vertices).collect();
+                        RawBufferData {
+                            vertex_data: vertex_data[offset as usize..(offset + size) as usize].to_vec(),
+                            index_data: bytemuck::cast_slice(&indices).to_vec(),
+                        }
+                    })
+                    .collect();
+                RawChunkData::new(lods)
+            })
+            .collect()
+    }
+
+    /// Runs both passes over `jobs`, which fill `vertex_count` vertices and
+    /// `point_count` density samples between them. Returns the vertices and
+    /// how many each job needed, which may be more than it had room for.
+    async fn run_jobs(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        jobs: &[VoxelJob],
+        vertex_count: u32,
+        point_count: u32,
+    ) -> (wgpu::Buffer, Vec<u32>) {
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("VoxelPipeline: Batch Vertices"),
+            size: (vertex_count * ChunkDimensions::VERTEX_SIZE) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let density_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("VoxelPipeline: Density"),
+            size: point_count as u64 * 4,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let jobs_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("VoxelPipeline: Jobs"),
+            contents: bytemuck::cast_slice(jobs),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let counts: Vec<[u32; 4]> = jobs.iter().map(|job| [0, 1, job.vertex_offset, 0]).collect();
+        let counts_size = jobs.len() as u64 * COUNT_SIZE;
+        let counts_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("VoxelPipeline: Counts"),
+            contents: bytemuck::cast_slice(&counts),
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("VoxelPipeline: BindGroup"),
+            layout: &self.layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: jobs_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 7,
+                    resource: density_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 8,
+                    resource: counts_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 9,
+                    resource: self.table_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("VoxelPipeline::gen_chunks"),
+        });
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("VoxelPipeline: ComputePass"),
+        });
+        cpass.set_bind_group(0, &bind_group, &[]);
+        // enough workgroups for the largest job, the rest exit early
+        let points = jobs.iter().map(VoxelJob::points).max().unwrap_or(0);
+        let cells = jobs.iter().map(VoxelJob::cells).max().unwrap_or(0);
+        cpass.set_pipeline(&self.density_pipeline);
+        cpass.dispatch_workgroups(points.div_ceil(64), jobs.len() as _, 1);
+        cpass.set_pipeline(&self.polygonise_pipeline);
+        cpass.dispatch_workgroups(cells.div_ceil(64), jobs.len() as _, 1);
+        drop(cpass);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let count_data = read_buffers(device, queue, &[(&counts_buffer, 0..counts_size)])
+            .await
+            .remove(0);
+        let counts: &[[u32; 4]] = bytemuck::cast_slice(&count_data);
+        (vertex_buffer, counts.iter().map(|count| count[0]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::gpu_test;
+
+    gpu_test! {
+        fn regrows_meshes_that_outgrow_their_room(device, queue) {
+            let options = Options {
+                seed: 5,
+                ..Default::default()
+            };
+            let coords = [ChunkCoord::new(0, 0), ChunkCoord::new(-7, 4)];
+            let roomy = VoxelPipeline::new(&device, &options);
+            let mut cramped = VoxelPipeline::new(&device, &options);
+            cramped.vertices_per_column = 1;
+
+            let expected = pollster::block_on(roomy.gen_chunks(&device, &queue, &coords));
+            let regrown = pollster::block_on(cramped.gen_chunks(&device, &queue, &coords));
+            for (expected, regrown) in expected.iter().zip(&regrown) {
+                assert_eq!(regrown.lods.len(), 1);
+                assert_eq!(regrown.lods[0].vertex_data, expected.lods[0].vertex_data);
+                assert_eq!(regrown.lods[0].index_data, expected.lods[0].index_data);
+            }
+        }
+    }
+}
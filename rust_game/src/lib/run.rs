@@ -1,14 +1,15 @@
-use std::{sync::{Arc, Mutex}, collections::HashMap, time::Instant, thread};
+use std::sync::Arc;
+
+use cgmath::EuclideanSpace;
 
-use cgmath::Vector2;
-use instant::Duration;
 use winit::{
     event::{DeviceEvent, ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::{ControlFlow, EventLoop},
     window::{CursorGrabMode, WindowBuilder},
 };
 
-use crate::lib::{State, pipelines::load_chunks::{ComputeWorldPipeline, ComputeWorld, RawBufferData}};
+use crate::lib::{State, options::Options, pipelines::chunk_jobs::ChunkJobs};
+use crate::world::ChunkView;
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
 pub async fn run() {
@@ -73,7 +74,8 @@ pub async fn run() {
         .request_device(
             &wgpu::DeviceDescriptor {
                 label: None,
-                features: wgpu::Features::empty(),
+                // chunks are drawn in one call where the adapter allows it
+                features: adapter.features() & wgpu::Features::MULTI_DRAW_INDIRECT,
                 limits: if cfg!(target_arch = "wasm32") {
                     wgpu::Limits::downlevel_webgl2_defaults()
                 } else {
@@ -85,7 +87,13 @@ pub async fn run() {
         .await
         .unwrap();
 
-    let chunk_size: Vector2<u32> = Vector2::new(32, 32);
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
     let compute_device = Arc::new(device);
     let compute_queue = Arc::new(queue);
     let render_device = Arc::clone(&compute_device);
@@ -96,52 +104,18 @@ pub async fn run() {
         adapter,
         render_device,
         render_queue,
-        chunk_size,
+        &options,
     ).await;
     state.window().set_visible(true);
 
-    // Define shared resources
-    let world_chunks: Arc<Mutex<HashMap<String, RawBufferData>>> = Arc::new(Mutex::new(HashMap::new()));
-    let world_chunks_shared = Arc::clone(&world_chunks);
-    let mut world_compute = ComputeWorld::new(chunk_size);
-    let world_pipeline = ComputeWorldPipeline::new(
-        &compute_device,
-    );
-
-    let requested_chunks: Arc<Mutex<HashMap<String, Vec<i32>>>> = Arc::new(Mutex::new(HashMap::new()));
-    let requested_chunks_shared = Arc::clone(&requested_chunks);
-
-    // Initiate Terrain Generation Loop
-    tokio::spawn(async move {
-        let mut last_execution_time = Instant::now();
-
-        loop {
-            let now = Instant::now();
-            if now - last_execution_time >= Duration::from_millis(500) {
-                // update requested chunks list
-                let mut temp_requested_chunks = HashMap::new();
-                if let Ok(x) = requested_chunks.lock() {
-                    temp_requested_chunks = x.clone();
-                }
-
-                world_compute.load_chunks(
-                    &compute_device, 
-                    &compute_queue, 
-                    &world_pipeline,
-                    temp_requested_chunks,
-                ).await;
-
-                // update shared chunks with newly created chunks
-                if let Ok(mut x) = world_chunks_shared.lock() {
-                    x.extend(world_compute.chunks.clone());
-                }
-
-                last_execution_time = now;
-            }
-
-            thread::sleep(Duration::from_millis(500));
+    // Generate terrain in the background
+    let mut chunk_jobs = match ChunkJobs::spawn(compute_device, compute_queue, &options) {
+        Ok(chunk_jobs) => chunk_jobs,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
         }
-    });
+    };
 
     // Initiate core game loop
     event_loop.run(move |event, _, control_flow| {
@@ -180,12 +154,7 @@ pub async fn run() {
                 }
             }
             Event::RedrawRequested(window_id) if window_id == state.window().id() => {
-                if let Ok(mut x) = world_chunks.lock() {
-                    if x.len() > 0 {
-                        state.world.raw_buffer_data = HashMap::new();
-                        state.world.raw_buffer_data.extend(x.drain());
-                    }
-                }
+                state.world.raw_buffer_data.extend(chunk_jobs.completed());
 
                 state.world.preflight_chunks(
                     (
@@ -195,14 +164,24 @@ pub async fn run() {
                     ).into(),
                 );
 
-                if let Ok(mut x) = requested_chunks_shared.lock() {
-                    *x = state.world.requested_chunks.clone();
-                }
+                // re-prioritise the requests for where the camera is now
+                let view = ChunkView::new(
+                    state.camera.position.to_vec().xz(),
+                    state.camera.forward().xz(),
+                    state.projection.half_fov_x(),
+                );
+                chunk_jobs.request(state.world.generation_queue(&view).collect());
 
                 let now = instant::Instant::now();
                 let dt = now - last_render_time;
                 last_render_time = now;
                 futures::executor::block_on(state.update(dt));
+                // save sculpted chunks once the stroke is over rather than every frame
+                if !state.sculptor.is_sculpting() {
+                    for (coord, chunk) in state.world.take_edited_chunks() {
+                        chunk_jobs.store(coord, chunk);
+                    }
+                }
                 match state.render() {
                     Ok(_) => {}
                     Err(wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated) => state.resize(state.size),
@@ -210,6 +189,7 @@ pub async fn run() {
                     Err(wgpu::SurfaceError::Timeout) => log::warn!("Surface timeout"),
                 }
             }
+            Event::LoopDestroyed => chunk_jobs.shutdown(),
             _ => {}
         }
     });
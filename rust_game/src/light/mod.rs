@@ -103,6 +103,7 @@ fn init_light_render_pipeline(
             Some(texture::Texture::DEPTH_FORMAT),
             &[model::ModelVertex::desc()],
             &shader,
+            wgpu::BlendState::REPLACE,
         )
     };
 
@@ -1,8 +1,4 @@
-mod lib;
-mod light;
-mod world;
-
-use crate::lib::run;
+use rust_game::lib::run;
 
 #[tokio::main]
 async fn main() {
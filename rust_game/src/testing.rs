@@ -0,0 +1,78 @@
+// Fixtures shared by the tests of every module.
+
+use cgmath::Vector2;
+
+use crate::lib::options::Options;
+use crate::lib::pipelines::load_chunks::ChunkMeshes;
+use crate::world::mesh::build_chunk;
+use crate::world::noise::TerrainVertex;
+use crate::world::{ChunkCoord, World};
+
+/// A device on the software adapter if there is one, otherwise any
+/// adapter. Panics when the machine has no adapter at all, so it is only
+/// for tests declared with `gpu_test!`.
+pub fn test_device() -> (wgpu::Device, wgpu::Queue) {
+    pollster::block_on(async {
+        let instance = wgpu::Instance::default();
+        let mut adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                force_fallback_adapter: true,
+                ..Default::default()
+            })
+            .await;
+        if adapter.is_none() {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions::default())
+                .await;
+        }
+        adapter
+            .expect("no graphics adapter")
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: None,
+                    features: wgpu::Features::empty(),
+                    limits: wgpu::Limits::downlevel_defaults(),
+                },
+                None,
+            )
+            .await
+            .expect("failed to open the graphics adapter")
+    })
+}
+
+/// A world with the chunks around the origin built on the CPU by `surface`
+/// and uploaded the way generated chunks are.
+pub fn loaded_world(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    options: &Options,
+    surface: impl Fn(Vector2<f32>) -> TerrainVertex,
+) -> World {
+    let mut world = World::new(device, options);
+    for coord in ChunkCoord::new(0, 0).within_radius(1) {
+        let chunk = build_chunk(coord, &options.chunk_dimensions, options.lod_count(), &surface);
+        world.raw_buffer_data.insert(coord, ChunkMeshes::Cpu(chunk));
+    }
+    while !world.raw_buffer_data.is_empty() {
+        world.ingest_chunk_data(device, queue);
+    }
+    world
+}
+
+/// A test that needs a graphics adapter, written as a function taking the
+/// device and queue of `test_device`. It is ignored unless asked for with
+/// `cargo test -- --include-ignored`, so a machine without an adapter
+/// reports it as not run rather than passed.
+macro_rules! gpu_test {
+    ($(#[$meta:meta])* fn $name:ident($device:pat, $queue:pat) $body:block) => {
+        $(#[$meta])*
+        #[test]
+        #[ignore = "needs a graphics adapter"]
+        fn $name() {
+            let ($device, $queue) = $crate::testing::test_device();
+            $body
+        }
+    };
+}
+
+pub(crate) use gpu_test;
@@ -0,0 +1,429 @@
+use std::ops::Range;
+
+use crate::lib::pipelines::load_chunks::{ChunkMeshes, PendingRead, RawBufferData, RawChunkData};
+
+use super::ChunkDimensions;
+
+/// Where one level of detail of a chunk sits in the arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MeshSlot {
+    /// First vertex of the mesh, added to every index.
+    pub base_vertex: u32,
+    /// Start of the indices shared by this level of detail.
+    pub first_index: u32,
+    /// Indices of the whole mesh, skirts included.
+    pub num_indices: u32,
+}
+
+/// Every level of detail of a chunk in the arena.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChunkSlot {
+    vertices: Range<u32>,
+    pub lods: Vec<MeshSlot>,
+}
+
+/// Chunks on their way back from the arena, see `ChunkArena::start_read`.
+pub struct ArenaRead {
+    read: PendingRead,
+    /// Levels of detail of each chunk, in order.
+    lod_counts: Vec<usize>,
+}
+
+impl ArenaRead {
+    pub fn size_bytes(&self) -> usize {
+        self.read.size_bytes()
+    }
+
+    /// The chunks, in the order they were asked for, once they have landed.
+    /// Like `PendingRead::try_take`, that needs the device polled.
+    pub fn try_finish(&mut self) -> Option<Vec<RawChunkData>> {
+        let mut data = self.read.try_take()?.into_iter();
+        let chunks = self
+            .lod_counts
+            .iter()
+            .map(|lods| {
+                let lods = (0..*lods)
+                    .map(|_| RawBufferData {
+                        vertex_data: data.next().unwrap(),
+                        index_data: data.next().unwrap(),
+                    })
+                    .collect();
+                RawChunkData::new(lods)
+            })
+            .collect();
+        Some(chunks)
+    }
+}
+
+/// Vertices of every loaded chunk in one buffer, drawn through one shared
+/// index buffer.
+///
+/// Each level of detail of a heightfield chunk has the same triangles as in
+/// any other chunk, and the indices of a voxel mesh just count up, so the
+/// indices of every mesh are the start of one list per level. Each chunk
+/// takes one range of the vertex buffer for all its levels, given back when
+/// it unloads. Both buffers grow when they run out of room.
+pub struct ChunkArena {
+    vertex_buffer: wgpu::Buffer,
+    /// Vertices the buffer has room for.
+    vertex_capacity: u32,
+    /// Unused ranges of vertices, in order and never touching.
+    free: Vec<Range<u32>>,
+    index_buffer: wgpu::Buffer,
+    index_capacity: u32,
+    index_len: u32,
+    /// Indices shared by each level of detail.
+    lod_indices: Vec<Range<u32>>,
+}
+
+impl ChunkArena {
+    pub fn new(device: &wgpu::Device, vertex_capacity: u32, index_capacity: u32) -> Self {
+        let vertex_capacity = vertex_capacity.max(1);
+        let index_capacity = index_capacity.max(1);
+        Self {
+            vertex_buffer: Self::create_vertex_buffer(device, vertex_capacity),
+            vertex_capacity,
+            free: std::iter::once(0..vertex_capacity).collect(),
+            index_buffer: Self::create_index_buffer(device, index_capacity),
+            index_capacity,
+            index_len: 0,
+            lod_indices: Vec::new(),
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ChunkArena: Vertices"),
+            size: (capacity * ChunkDimensions::VERTEX_SIZE) as u64,
+            // rewritten in place when the terrain is sculpted, copied over
+            // when the buffer grows, and read by `RaycastPipeline`
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_index_buffer(device: &wgpu::Device, capacity: u32) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ChunkArena: Indices"),
+            size: (capacity * ChunkDimensions::INDEX_SIZE) as u64,
+            usage: wgpu::BufferUsages::INDEX
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST
+                | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> &wgpu::Buffer {
+        &self.index_buffer
+    }
+
+    /// Uploads every level of detail of a chunk, or copies it over when it
+    /// is already on the GPU.
+    pub fn insert(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, chunk: &ChunkMeshes) -> ChunkSlot {
+        let sizes = chunk.mesh_sizes();
+        let start = self.allocate(device, queue, sizes.iter().map(|size| size.num_vertices).sum());
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("ChunkArena::insert"),
+        });
+        let mut lods = Vec::with_capacity(sizes.len());
+        // where each level starts, in the arena and in the chunk
+        let (mut base_vertex, mut source_vertex, mut source_index) = (start, 0, 0);
+        for (level, size) in sizes.iter().enumerate() {
+            let first_index = self.shared_indices(device, queue, level, size.num_indices, |buffer, offset| {
+                match chunk {
+                    ChunkMeshes::Cpu(data) => queue.write_buffer(buffer, offset, &data.lods[level].index_data),
+                    ChunkMeshes::Gpu(data) => copy_buffer(
+                        device,
+                        queue,
+                        (&data.index_buffer, (source_index * ChunkDimensions::INDEX_SIZE) as u64),
+                        (buffer, offset),
+                        (size.num_indices * ChunkDimensions::INDEX_SIZE) as u64,
+                    ),
+                }
+            });
+            let slot = MeshSlot {
+                base_vertex,
+                first_index,
+                num_indices: size.num_indices,
+            };
+            match chunk {
+                ChunkMeshes::Cpu(data) => self.write_vertices(queue, &slot, &data.lods[level].vertex_data),
+                ChunkMeshes::Gpu(data) if size.num_vertices > 0 => encoder.copy_buffer_to_buffer(
+                    &data.vertex_buffer,
+                    (source_vertex * ChunkDimensions::VERTEX_SIZE) as u64,
+                    &self.vertex_buffer,
+                    (base_vertex * ChunkDimensions::VERTEX_SIZE) as u64,
+                    (size.num_vertices * ChunkDimensions::VERTEX_SIZE) as u64,
+                ),
+                ChunkMeshes::Gpu(_) => {}
+            }
+            lods.push(slot);
+            base_vertex += size.num_vertices;
+            source_vertex += size.num_vertices;
+            source_index += size.num_indices;
+        }
+        if let ChunkMeshes::Gpu(_) = chunk {
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+
+        ChunkSlot {
+            vertices: start..base_vertex,
+            lods,
+        }
+    }
+
+    /// Starts reading chunks back out of the arena, as they were inserted or
+    /// last written. They land on the CPU in the background, see
+    /// `ArenaRead::try_finish`.
+    pub fn start_read(&self, device: &wgpu::Device, queue: &wgpu::Queue, slots: &[&ChunkSlot]) -> ArenaRead {
+        let mut ranges = Vec::new();
+        for slot in slots {
+            let ends = slot.lods.iter().skip(1).map(|lod| lod.base_vertex).chain([slot.vertices.end]);
+            for (lod, end) in slot.lods.iter().zip(ends) {
+                let vertices = lod.base_vertex as u64..end as u64;
+                let indices = lod.first_index as u64..(lod.first_index + lod.num_indices) as u64;
+                let vertex_size = ChunkDimensions::VERTEX_SIZE as u64;
+                let index_size = ChunkDimensions::INDEX_SIZE as u64;
+                ranges.push((&self.vertex_buffer, vertices.start * vertex_size..vertices.end * vertex_size));
+                ranges.push((&self.index_buffer, indices.start * index_size..indices.end * index_size));
+            }
+        }
+
+        ArenaRead {
+            read: PendingRead::new(device, queue, &ranges),
+            lod_counts: slots.iter().map(|slot| slot.lods.len()).collect(),
+        }
+    }
+
+    /// Frees the vertices of a chunk for reuse.
+    pub fn remove(&mut self, slot: &ChunkSlot) {
+        let range = slot.vertices.clone();
+        if range.is_empty() {
+            return;
+        }
+        let i = self.free.partition_point(|free| free.start < range.start);
+        self.free.insert(i, range);
+        // merge with the neighbours it touches
+        if i + 1 < self.free.len() && self.free[i].end == self.free[i + 1].start {
+            self.free[i].end = self.free.remove(i + 1).end;
+        }
+        if i > 0 && self.free[i - 1].end == self.free[i].start {
+            self.free[i - 1].end = self.free.remove(i).end;
+        }
+    }
+
+    /// Replaces the vertices of a mesh already in the arena, which must keep
+    /// its vertex count.
+    pub fn write_vertices(&self, queue: &wgpu::Queue, slot: &MeshSlot, vertex_data: &[u8]) {
+        if vertex_data.is_empty() {
+            return;
+        }
+        let offset = (slot.base_vertex * ChunkDimensions::VERTEX_SIZE) as u64;
+        queue.write_buffer(&self.vertex_buffer, offset, vertex_data);
+    }
+
+    /// First free range of `count` vertices, growing the buffer if none is
+    /// long enough.
+    fn allocate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, count: u32) -> u32 {
+        if count == 0 {
+            return 0;
+        }
+        let i = match self.free.iter().position(|free| free.len() as u32 >= count) {
+            Some(i) => i,
+            None => {
+                self.grow_vertices(device, queue, count);
+                self.free.len() - 1
+            }
+        };
+
+        let start = self.free[i].start;
+        self.free[i].start += count;
+        if self.free[i].is_empty() {
+            self.free.remove(i);
+        }
+        start
+    }
+
+    /// Doubles the vertex buffer until the free range at its end holds
+    /// `count` vertices.
+    fn grow_vertices(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, count: u32) {
+        let old_capacity = self.vertex_capacity;
+        let tail = match self.free.last() {
+            Some(free) if free.end == old_capacity => free.len() as u32,
+            _ => 0,
+        };
+        let mut capacity = old_capacity;
+        while capacity - old_capacity + tail < count {
+            capacity *= 2;
+        }
+
+        let buffer = Self::create_vertex_buffer(device, capacity);
+        let size = old_capacity * ChunkDimensions::VERTEX_SIZE;
+        copy_buffer(device, queue, (&self.vertex_buffer, 0), (&buffer, 0), size as u64);
+        self.vertex_buffer = buffer;
+        self.vertex_capacity = capacity;
+        match self.free.last_mut() {
+            Some(free) if free.end == old_capacity => free.end = capacity,
+            _ => self.free.push(old_capacity..capacity),
+        }
+    }
+
+    /// Start of the shared indices of a level of detail, after making sure
+    /// there are `count` of them. `upload` writes the indices of the mesh
+    /// to the given buffer and byte offset, if they are needed.
+    fn shared_indices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        level: usize,
+        count: u32,
+        upload: impl FnOnce(&wgpu::Buffer, u64),
+    ) -> u32 {
+        if level < self.lod_indices.len() && self.lod_indices[level].len() as u32 >= count {
+            return self.lod_indices[level].start;
+        }
+
+        // a longer list than any before it, which the shorter ones start
+        // with, so it takes their place
+        if self.index_len + count > self.index_capacity {
+            let mut capacity = self.index_capacity;
+            while capacity < self.index_len + count {
+                capacity *= 2;
+            }
+            let buffer = Self::create_index_buffer(device, capacity);
+            let size = self.index_len * ChunkDimensions::INDEX_SIZE;
+            copy_buffer(device, queue, (&self.index_buffer, 0), (&buffer, 0), size as u64);
+            self.index_buffer = buffer;
+            self.index_capacity = capacity;
+        }
+        let start = self.index_len;
+        upload(&self.index_buffer, (start * ChunkDimensions::INDEX_SIZE) as u64);
+        self.index_len += count;
+
+        if level >= self.lod_indices.len() {
+            self.lod_indices.resize(level + 1, 0..0);
+        }
+        self.lod_indices[level] = start..start + count;
+        start
+    }
+}
+
+/// Copies `size` bytes between two buffers, each given with the byte offset
+/// to copy from or to.
+fn copy_buffer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    (from, from_offset): (&wgpu::Buffer, u64),
+    (to, to_offset): (&wgpu::Buffer, u64),
+    size: u64,
+) {
+    if size == 0 {
+        return;
+    }
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("ChunkArena::copy_buffer"),
+    });
+    encoder.copy_buffer_to_buffer(from, from_offset, to, to_offset, size);
+    queue.submit(std::iter::once(encoder.finish()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::lib::options::Options;
+    use crate::lib::pipelines::load_chunks::ComputeWorldPipeline;
+    use crate::testing::gpu_test;
+    use crate::world::mesh::build_chunk;
+    use crate::world::noise::terrain_vertex;
+    use crate::world::ChunkCoord;
+
+    /// Reads chunks back out of the arena, waiting for them.
+    fn read_chunks(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        arena: &ChunkArena,
+        slots: &[&ChunkSlot],
+    ) -> Vec<RawChunkData> {
+        let mut read = arena.start_read(device, queue, slots);
+        device.poll(wgpu::Maintain::Wait);
+        read.try_finish().unwrap()
+    }
+
+    fn assert_same_chunks(read: &[RawChunkData], inserted: &[RawChunkData]) {
+        for (read, inserted) in read.iter().zip(inserted) {
+            assert_eq!(read.lods.len(), inserted.lods.len());
+            for (read, inserted) in read.lods.iter().zip(&inserted.lods) {
+                assert_eq!(read.vertex_data, inserted.vertex_data);
+                assert_eq!(read.index_data, inserted.index_data);
+            }
+            assert_eq!(read.height_range, inserted.height_range);
+        }
+    }
+
+    gpu_test! {
+        fn keeps_chunks_through_growth_and_reuse(device, queue) {
+            let options = Options::default();
+            let chunk = |x, z| {
+                build_chunk(ChunkCoord::new(x, z), &options.chunk_dimensions, options.lod_count(), |p| {
+                    terrain_vertex(p, options.seed)
+                })
+            };
+
+            // room for nothing, so every insert has to grow the buffers
+            let mut arena = ChunkArena::new(&device, 1, 1);
+            let mut chunks: Vec<_> = [(0, 0), (1, 0), (0, 1)].iter().map(|&(x, z)| chunk(x, z)).collect();
+            let mut slots: Vec<_> = chunks
+                .iter()
+                .map(|chunk| arena.insert(&device, &queue, &ChunkMeshes::Cpu(chunk.clone())))
+                .collect();
+
+            // the same size again fits where the unloaded chunk was
+            arena.remove(&slots[1]);
+            chunks[1] = chunk(-3, 2);
+            let slot = arena.insert(&device, &queue, &ChunkMeshes::Cpu(chunks[1].clone()));
+            assert_eq!(slot.vertices, slots[1].vertices);
+            slots[1] = slot;
+
+            let slots: Vec<&ChunkSlot> = slots.iter().collect();
+            let read = read_chunks(&device, &queue, &arena, &slots);
+            assert_same_chunks(&read, &chunks);
+        }
+    }
+
+    gpu_test! {
+        fn copies_chunks_left_on_the_gpu(device, queue) {
+            let options = Options {
+                seed: 9,
+                ..Default::default()
+            };
+            let coords = [ChunkCoord::new(0, 0), ChunkCoord::new(5, -2)];
+            let pipeline = ComputeWorldPipeline::new(&device, &options);
+            let read_back = pollster::block_on(pipeline.gen_chunks(&device, &queue, &coords));
+            let on_gpu = pollster::block_on(pipeline.gen_gpu_chunks(&device, &queue, &coords));
+
+            for (gpu, cpu) in on_gpu.iter().zip(&read_back) {
+                assert_eq!(gpu.height_range, cpu.height_range);
+            }
+
+            let mut arena = ChunkArena::new(&device, 1, 1);
+            let slots: Vec<ChunkSlot> = on_gpu
+                .into_iter()
+                .map(|chunk| arena.insert(&device, &queue, &ChunkMeshes::Gpu(Arc::new(chunk))))
+                .collect();
+            let slots: Vec<&ChunkSlot> = slots.iter().collect();
+            let read = read_chunks(&device, &queue, &arena, &slots);
+            assert_same_chunks(&read, &read_back);
+        }
+    }
+}
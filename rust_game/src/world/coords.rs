@@ -0,0 +1,167 @@
+use cgmath::Vector2;
+
+/// Size of every chunk mesh on the vertex grid and in world space.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChunkDimensions {
+    /// Number of quads along each side of the chunk.
+    pub resolution: Vector2<u32>,
+    /// World-space size of the chunk along each side.
+    pub extent: Vector2<f32>,
+}
+
+impl ChunkDimensions {
+    /// Bytes per terrain vertex: position and normal, each padded to 16 bytes.
+    pub const VERTEX_SIZE: u32 = 8 * std::mem::size_of::<f32>() as u32;
+    pub const INDEX_SIZE: u32 = std::mem::size_of::<u32>() as u32;
+    /// Depth of the skirts below each chunk edge, in multiples of the vertex spacing.
+    const SKIRT_DEPTH_PER_SPACING: f32 = 2.0;
+
+    pub fn new(resolution: Vector2<u32>, extent: Vector2<f32>) -> Self {
+        Self { resolution, extent }
+    }
+
+    /// Dimensions of the given level of detail, which halves the resolution
+    /// per level while covering the same extent.
+    pub fn lod(&self, level: u32) -> Self {
+        Self {
+            resolution: self.resolution.map(|r| (r >> level).max(1)),
+            extent: self.extent,
+        }
+    }
+
+    /// Number of levels of detail the resolution can be halved into evenly.
+    pub fn max_lod_count(&self) -> u32 {
+        self.resolution.x.min(self.resolution.y).trailing_zeros() + 1
+    }
+
+    /// World-space distance between neighbouring vertices along each side.
+    pub fn spacing(&self) -> Vector2<f32> {
+        Vector2::new(
+            self.extent.x / self.resolution.x as f32,
+            self.extent.y / self.resolution.y as f32,
+        )
+    }
+
+    /// Position of grid vertex `index` relative to the chunk's corner. Grid
+    /// vertices are laid out row by row along x.
+    pub fn grid_position(&self, index: u32) -> Vector2<f32> {
+        let row = self.resolution.x + 1;
+        let spacing = self.spacing();
+        Vector2::new(
+            (index % row) as f32 * spacing.x,
+            (index / row) as f32 * spacing.y,
+        )
+    }
+
+    pub fn skirt_depth(&self) -> f32 {
+        self.spacing().x * Self::SKIRT_DEPTH_PER_SPACING
+    }
+
+    pub fn grid_vertices(&self) -> u32 {
+        (self.resolution.x + 1) * (self.resolution.y + 1)
+    }
+
+    pub fn skirt_vertices(&self) -> u32 {
+        2 * (self.resolution.x + 1) + 2 * (self.resolution.y + 1)
+    }
+
+    pub fn num_vertices(&self) -> u32 {
+        self.grid_vertices() + self.skirt_vertices()
+    }
+
+    /// Indices of the grid itself; the skirt indices follow them.
+    pub fn grid_indices(&self) -> u32 {
+        self.resolution.x * self.resolution.y * 6
+    }
+
+    pub fn skirt_indices(&self) -> u32 {
+        (2 * self.resolution.x + 2 * self.resolution.y) * 6
+    }
+
+    pub fn num_indices(&self) -> u32 {
+        self.grid_indices() + self.skirt_indices()
+    }
+
+    pub fn vertex_buffer_size(&self) -> u64 {
+        (self.num_vertices() * Self::VERTEX_SIZE) as u64
+    }
+
+    pub fn index_buffer_size(&self) -> u64 {
+        (self.num_indices() * Self::INDEX_SIZE) as u64
+    }
+}
+
+/// Integer position of a chunk on the chunk grid.
+///
+/// Chunk `(0, 0)` covers world positions `[0, extent)` on both axes,
+/// chunk `(-1, 0)` covers `[-extent, 0)` on x, and so on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkCoord {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ChunkCoord {
+    pub const fn new(x: i32, z: i32) -> Self {
+        Self { x, z }
+    }
+
+    /// Chunk containing the world position `(x, z)`.
+    ///
+    /// Uses floor division so positions just below zero land in chunk `-1`
+    /// instead of being truncated towards chunk `0`.
+    pub fn from_world(x: f32, z: f32, dimensions: &ChunkDimensions) -> Self {
+        Self {
+            x: (x / dimensions.extent.x).floor() as i32,
+            z: (z / dimensions.extent.y).floor() as i32,
+        }
+    }
+
+    /// World position of the chunk's minimum corner.
+    pub fn corner(&self, dimensions: &ChunkDimensions) -> Vector2<f32> {
+        Vector2::new(
+            self.x as f32 * dimensions.extent.x,
+            self.z as f32 * dimensions.extent.y,
+        )
+    }
+
+    /// World position of the chunk's centre.
+    pub fn center(&self, dimensions: &ChunkDimensions) -> Vector2<f32> {
+        Vector2::new(
+            (self.x as f32 + 0.5) * dimensions.extent.x,
+            (self.z as f32 + 0.5) * dimensions.extent.y,
+        )
+    }
+
+    pub fn offset(&self, dx: i32, dz: i32) -> Self {
+        Self::new(self.x + dx, self.z + dz)
+    }
+
+    /// Squared distance to `other`, measured in chunks.
+    pub fn distance_squared(&self, other: ChunkCoord) -> i32 {
+        let dx = self.x - other.x;
+        let dz = self.z - other.z;
+        dx * dx + dz * dz
+    }
+
+    /// The eight chunks surrounding this one.
+    pub fn neighbours(&self) -> impl Iterator<Item = ChunkCoord> {
+        let center = *self;
+        (-1..=1)
+            .flat_map(move |dz| (-1..=1).map(move |dx| center.offset(dx, dz)))
+            .filter(move |coord| *coord != center)
+    }
+
+    /// Whether `other` is within `radius` chunks of this one.
+    pub fn is_within_radius(&self, other: ChunkCoord, radius: i32) -> bool {
+        self.distance_squared(other) <= radius * radius + 1
+    }
+
+    /// Every chunk within `radius` chunks of this one, in row order.
+    pub fn within_radius(&self, radius: i32) -> impl Iterator<Item = ChunkCoord> {
+        let center = *self;
+        (-radius..=radius)
+            .flat_map(move |dz| (-radius..=radius).map(move |dx| center.offset(dx, dz)))
+            .filter(move |coord| center.is_within_radius(*coord, radius))
+    }
+}
@@ -0,0 +1,321 @@
+// CPU copy of the erosion passes in `gen_terrain.wgsl`, for eroding terrain
+// without a GPU. Has to be kept in step with the WGSL.
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::lib::options::ErosionOptions;
+use crate::lib::pipelines::load_chunks::RawChunkData;
+
+use super::mesh::build_chunk;
+use super::noise::{biome, climate, terrain_point, TerrainVertex};
+use super::{ChunkCoord, ChunkDimensions};
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Cell {
+    height: f32,
+    water: f32,
+    sediment: f32,
+}
+
+/// What leaves a cell towards +x, -x, +z and -z.
+#[derive(Debug, Clone, Copy, Default)]
+struct Flow {
+    water: [f32; 4],
+    sediment: [f32; 4],
+    slide: [f32; 4],
+}
+
+fn sum(v: [f32; 4]) -> f32 {
+    v[0] + v[1] + v[2] + v[3]
+}
+
+/// The grid a chunk is eroded on: its most detailed level plus
+/// `ErosionOptions::margin` cells all round, row by row along x.
+struct Domain {
+    size: Vector2<usize>,
+    spacing: Vector2<f32>,
+    margin: usize,
+    cells: Vec<Cell>,
+}
+
+impl Domain {
+    fn new(coord: ChunkCoord, dimensions: &ChunkDimensions, seed: u32, margin: u32) -> Self {
+        let size = (dimensions.resolution + Vector2::new(1, 1) * (2 * margin + 1)).cast().unwrap();
+        let spacing = dimensions.spacing();
+        // counted in cells from the world origin, like `domain_point` in
+        // `gen_terrain.wgsl`, so neighbouring chunks sample the same points
+        let origin = Vector2::new(
+            coord.x * dimensions.resolution.x as i32,
+            coord.z * dimensions.resolution.y as i32,
+        ) - Vector2::new(margin, margin).cast().unwrap();
+
+        let cells = (0..size.x * size.y)
+            .map(|i| {
+                let grid = origin + Vector2::new(i % size.x, i / size.x).cast().unwrap();
+                let p = Vector2::new(grid.x as f32 * spacing.x, grid.y as f32 * spacing.y);
+                Cell {
+                    height: terrain_point(p, seed).y,
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        Self {
+            size,
+            spacing,
+            margin: margin as usize,
+            cells,
+        }
+    }
+
+    fn index(&self, x: usize, z: usize) -> usize {
+        z * self.size.x + x
+    }
+
+    /// Index of the cell next to `i` towards +x, -x, +z or -z, if it isn't
+    /// past the edge of the grid.
+    fn neighbour(&self, i: usize, direction: usize) -> Option<usize> {
+        let (x, z) = (i % self.size.x, i / self.size.x);
+        match direction {
+            0 if x + 1 < self.size.x => Some(i + 1),
+            1 if x > 0 => Some(i - 1),
+            2 if z + 1 < self.size.y => Some(i + self.size.x),
+            3 if z > 0 => Some(i - self.size.x),
+            _ => None,
+        }
+    }
+
+    /// One round of `erosion_flow` then `erosion_settle`.
+    fn step(&mut self, options: &ErosionOptions) {
+        let flows: Vec<Flow> = (0..self.cells.len()).map(|i| self.flow(i, options)).collect();
+        self.cells = (0..self.cells.len())
+            .map(|i| self.settle(i, &flows, options))
+            .collect();
+    }
+
+    fn flow(&self, i: usize, options: &ErosionOptions) -> Flow {
+        let here = self.cells[i];
+        let water = here.water + options.rain;
+
+        let mut drop = [0.0; 4];
+        let mut excess = [0.0; 4];
+        for direction in 0..4 {
+            let Some(n) = self.neighbour(i, direction) else {
+                continue;
+            };
+            let there = self.cells[n];
+            drop[direction] =
+                (here.height + water - (there.height + there.water + options.rain)).max(0.0);
+            let distance = if direction < 2 { self.spacing.x } else { self.spacing.y };
+            excess[direction] = (here.height - there.height - options.talus * distance).max(0.0);
+        }
+
+        let mut flow = Flow::default();
+        let total_drop = sum(drop);
+        if total_drop > 0.0 && water > 0.0 {
+            // at most half the difference, so water levels meet rather than swap
+            let share = water.min(total_drop * 0.5) / total_drop;
+            flow.water = drop.map(|d| d * share);
+            flow.sediment = flow.water.map(|w| w * (here.sediment / water));
+        }
+        let total_excess = sum(excess);
+        if total_excess > 0.0 {
+            let steepest = excess[0].max(excess[1]).max(excess[2].max(excess[3]));
+            let slid = options.thermal_rate * steepest * 0.5;
+            flow.slide = excess.map(|e| e * (slid / total_excess));
+        }
+        flow
+    }
+
+    fn settle(&self, i: usize, flows: &[Flow], options: &ErosionOptions) -> Cell {
+        let here = self.cells[i];
+        let out = flows[i];
+
+        let mut water = here.water + options.rain - sum(out.water);
+        let mut sediment = here.sediment - sum(out.sediment);
+        let mut height = here.height - sum(out.slide);
+        for direction in 0..4 {
+            let Some(n) = self.neighbour(i, direction) else {
+                continue;
+            };
+            // the neighbour's flow back the way we came
+            let back = direction ^ 1;
+            water += flows[n].water[back];
+            sediment += flows[n].sediment[back];
+            height += flows[n].slide[back];
+        }
+
+        let capacity = options.sediment_capacity * sum(out.water);
+        if sediment > capacity {
+            let deposited = options.deposition_rate * (sediment - capacity);
+            height += deposited;
+            sediment -= deposited;
+        } else {
+            let eroded = options.erosion_rate * (capacity - sediment);
+            height -= eroded;
+            sediment += eroded;
+        }
+
+        Cell {
+            height,
+            water: water * (1.0 - options.evaporation),
+            sediment,
+        }
+    }
+
+    /// Height once the water has gone and the sediment it carried has settled.
+    fn eroded_height(&self, x: usize, z: usize) -> f32 {
+        let cell = self.cells[self.index(x, z)];
+        cell.height + cell.sediment
+    }
+
+    /// Eroded surface at grid vertex `(x, z)` of the chunk, with the normal
+    /// from the eroded slopes.
+    fn vertex(&self, p: Vector2<f32>, x: usize, z: usize, seed: u32) -> TerrainVertex {
+        let (x, z) = (x + self.margin, z + self.margin);
+        let dx = self.eroded_height(x + 1, z) - self.eroded_height(x - 1, z);
+        let dz = self.eroded_height(x, z + 1) - self.eroded_height(x, z - 1);
+
+        TerrainVertex {
+            position: Vector3::new(p.x, self.eroded_height(x, z), p.y),
+            normal: Vector3::new(-dx / (2.0 * self.spacing.x), 1.0, -dz / (2.0 * self.spacing.y))
+                .normalize(),
+            biome: biome(climate(p, seed)),
+        }
+    }
+}
+
+/// Generates and erodes a chunk on the CPU, matching what the GPU erosion
+/// passes in `gen_terrain.wgsl` produce.
+pub fn erode_chunk(
+    coord: ChunkCoord,
+    dimensions: &ChunkDimensions,
+    lod_count: u32,
+    seed: u32,
+    options: &ErosionOptions,
+) -> RawChunkData {
+    let mut domain = Domain::new(coord, dimensions, seed, options.margin());
+    for _ in 0..options.iterations {
+        domain.step(options);
+    }
+
+    let corner = coord.corner(dimensions);
+    let spacing = dimensions.spacing();
+    build_chunk(coord, dimensions, lod_count, |p| {
+        let cell = (p - corner).zip(spacing, |offset, spacing| (offset / spacing).round() as usize);
+        domain.vertex(p, cell.x, cell.y, seed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::options::Options;
+    use crate::lib::pipelines::load_chunks::ComputeWorldPipeline;
+    use crate::testing::gpu_test;
+
+    const POSITION_TOLERANCE: f32 = 5e-3;
+    const NORMAL_TOLERANCE: f32 = 5e-3;
+
+    fn eroded_options(seed: u32) -> Options {
+        Options {
+            seed,
+            erosion: Some(ErosionOptions::default()),
+            ..Default::default()
+        }
+    }
+
+    fn erode(options: &Options, coord: ChunkCoord) -> RawChunkData {
+        erode_chunk(
+            coord,
+            &options.chunk_dimensions,
+            options.lod_count(),
+            options.seed,
+            options.erosion.as_ref().unwrap(),
+        )
+    }
+
+    gpu_test! {
+        fn matches_gpu(device, queue) {
+            // the GPU and CPU noise differ slightly, and every round of erosion
+            // makes more of it, so only a few rounds can be compared closely
+            let options = Options {
+                erosion: Some(ErosionOptions {
+                    iterations: 4,
+                    ..Default::default()
+                }),
+                ..eroded_options(7)
+            };
+            let coords = [ChunkCoord::new(0, 0), ChunkCoord::new(-4, 9)];
+            let pipeline = ComputeWorldPipeline::new(&device, &options);
+            let gpu_chunks = pollster::block_on(pipeline.gen_chunks(&device, &queue, &coords));
+
+            for (coord, gpu) in coords.into_iter().zip(gpu_chunks) {
+                let cpu = erode(&options, coord);
+                for (level, (cpu, gpu)) in cpu.lods.iter().zip(&gpu.lods).enumerate() {
+                    let num_vertices = options.chunk_dimensions.lod(level as u32).num_vertices();
+                    for i in 0..num_vertices {
+                        let (cpu, gpu) = (cpu.vertex(i), gpu.vertex(i));
+                        assert!(
+                            (cpu.position - gpu.position).magnitude() < POSITION_TOLERANCE,
+                            "chunk {:?} LOD {} vertex {} position: cpu {:?}, gpu {:?}",
+                            coord,
+                            level,
+                            i,
+                            cpu.position,
+                            gpu.position,
+                        );
+                        assert!(
+                            (cpu.normal - gpu.normal).magnitude() < NORMAL_TOLERANCE,
+                            "chunk {:?} LOD {} vertex {} normal: cpu {:?}, gpu {:?}",
+                            coord,
+                            level,
+                            i,
+                            cpu.normal,
+                            gpu.normal,
+                        );
+                        assert_eq!(cpu.biome, gpu.biome);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_share_edges() {
+        let options = eroded_options(3);
+        let dimensions = options.chunk_dimensions;
+        let (rx, rz) = (dimensions.resolution.x, dimensions.resolution.y);
+        let row = rx + 1;
+        let chunk = erode(&options, ChunkCoord::new(2, 5));
+        let east = erode(&options, ChunkCoord::new(3, 5));
+        let south = erode(&options, ChunkCoord::new(2, 6));
+
+        for z in 0..=rz {
+            let (a, b) = (chunk.lods[0].vertex(z * row + rx), east.lods[0].vertex(z * row));
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.normal, b.normal);
+        }
+        for x in 0..=rx {
+            let (a, b) = (chunk.lods[0].vertex(rz * row + x), south.lods[0].vertex(x));
+            assert_eq!(a.position, b.position);
+            assert_eq!(a.normal, b.normal);
+        }
+    }
+
+    #[test]
+    fn changes_the_terrain() {
+        let options = eroded_options(11);
+        let dimensions = options.chunk_dimensions;
+        let coord = ChunkCoord::new(1, 1);
+        let chunk = erode(&options, coord);
+
+        let largest_change = (0..dimensions.grid_vertices())
+            .map(|i| {
+                let p = coord.corner(&dimensions) + dimensions.grid_position(i);
+                (chunk.lods[0].vertex(i).position.y - terrain_point(p, options.seed).y).abs()
+            })
+            .fold(0.0, f32::max);
+        assert!(largest_change > 0.1, "erosion moved the ground at most {}", largest_change);
+    }
+}
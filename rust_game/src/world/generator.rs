@@ -0,0 +1,282 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use cgmath::{Vector2, Vector3};
+
+use crate::lib::options::{ErosionOptions, Options, TerrainSource};
+use crate::lib::pipelines::load_chunks::{ChunkMeshes, ComputeWorldPipeline, RawChunkData};
+use crate::lib::pipelines::voxel::VoxelPipeline;
+
+use super::erosion::erode_chunk;
+use super::mesh::build_chunk;
+use super::noise::{terrain_vertex, Biome, TerrainVertex};
+use super::{ChunkCoord, ChunkDimensions, Heightmap};
+
+/// Source of chunk meshes. Whatever it reads or computes, it hands back every
+/// level of detail of a chunk in the vertex layout `gen_terrain.wgsl` writes,
+/// as a height grid unless it is a `VoxelGenerator`, which only makes the
+/// most detailed level.
+pub trait TerrainGenerator: Send {
+    /// Every level of detail of each chunk, in the order asked for.
+    fn generate(&mut self, coords: &[ChunkCoord]) -> Vec<RawChunkData>;
+
+    /// Like `generate`, but chunks made on the GPU may be left there for
+    /// drawing, when nothing needs them on the CPU.
+    fn generate_meshes(&mut self, coords: &[ChunkCoord]) -> Vec<ChunkMeshes> {
+        self.generate(coords).into_iter().map(ChunkMeshes::Cpu).collect()
+    }
+
+    /// Most chunks `generate` can be asked for at once.
+    fn max_batch_size(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Whether generated chunks should be saved. Saves are only told apart
+    /// by seed and chunk layout, so sources that don't follow the seed
+    /// must not share them.
+    fn saves_chunks(&self) -> bool {
+        true
+    }
+}
+
+/// The generator picked by `options.terrain`. The device and queue are only
+/// used when generating on the GPU.
+pub fn new_generator(
+    options: &Options,
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+) -> Result<Box<dyn TerrainGenerator>> {
+    Ok(match &options.terrain {
+        TerrainSource::GpuNoise => Box::new(GpuNoiseGenerator::new(device, queue, options)),
+        TerrainSource::CpuNoise => Box::new(CpuNoiseGenerator::new(options)),
+        TerrainSource::Flat(height) => Box::new(FlatGenerator::new(options, *height)),
+        TerrainSource::Heightmap(heightmap) => Box::new(HeightmapGenerator {
+            heightmap: Heightmap::load(heightmap)?,
+            chunk_dimensions: options.chunk_dimensions,
+            lod_count: options.lod_count(),
+        }),
+        TerrainSource::Voxel => Box::new(VoxelGenerator::new(device, queue, options)),
+    })
+}
+
+/// Noise generated in batches by `ComputeWorldPipeline`.
+pub struct GpuNoiseGenerator {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: ComputeWorldPipeline,
+}
+
+impl GpuNoiseGenerator {
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, options: &Options) -> Self {
+        let pipeline = ComputeWorldPipeline::new(&device, options);
+        Self {
+            device,
+            queue,
+            pipeline,
+        }
+    }
+}
+
+impl TerrainGenerator for GpuNoiseGenerator {
+    fn generate(&mut self, coords: &[ChunkCoord]) -> Vec<RawChunkData> {
+        pollster::block_on(self.pipeline.gen_chunks(&self.device, &self.queue, coords))
+    }
+
+    fn generate_meshes(&mut self, coords: &[ChunkCoord]) -> Vec<ChunkMeshes> {
+        pollster::block_on(self.pipeline.gen_gpu_chunks(&self.device, &self.queue, coords))
+            .into_iter()
+            .map(|chunk| ChunkMeshes::Gpu(Arc::new(chunk)))
+            .collect()
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.pipeline.max_batch_size()
+    }
+}
+
+/// The noise of `GpuNoiseGenerator` computed on the CPU, one chunk at a time.
+pub struct CpuNoiseGenerator {
+    seed: u32,
+    chunk_dimensions: ChunkDimensions,
+    lod_count: u32,
+    erosion: Option<ErosionOptions>,
+}
+
+impl CpuNoiseGenerator {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            seed: options.seed,
+            chunk_dimensions: options.chunk_dimensions,
+            lod_count: options.lod_count(),
+            erosion: options.erosion,
+        }
+    }
+}
+
+impl TerrainGenerator for CpuNoiseGenerator {
+    fn generate(&mut self, coords: &[ChunkCoord]) -> Vec<RawChunkData> {
+        let (dimensions, lod_count, seed) = (&self.chunk_dimensions, self.lod_count, self.seed);
+        coords
+            .iter()
+            .map(|coord| match &self.erosion {
+                Some(erosion) => erode_chunk(*coord, dimensions, lod_count, seed, erosion),
+                None => build_chunk(*coord, dimensions, lod_count, |p| terrain_vertex(p, seed)),
+            })
+            .collect()
+    }
+}
+
+/// Level ground everywhere.
+pub struct FlatGenerator {
+    height: f32,
+    chunk_dimensions: ChunkDimensions,
+    lod_count: u32,
+}
+
+impl FlatGenerator {
+    pub fn new(options: &Options, height: f32) -> Self {
+        Self {
+            height,
+            chunk_dimensions: options.chunk_dimensions,
+            lod_count: options.lod_count(),
+        }
+    }
+
+    fn vertex(&self, p: Vector2<f32>) -> TerrainVertex {
+        TerrainVertex {
+            position: Vector3::new(p.x, self.height, p.y),
+            normal: Vector3::unit_y(),
+            biome: Biome::Plains,
+        }
+    }
+}
+
+impl TerrainGenerator for FlatGenerator {
+    fn generate(&mut self, coords: &[ChunkCoord]) -> Vec<RawChunkData> {
+        coords
+            .iter()
+            .map(|coord| build_chunk(*coord, &self.chunk_dimensions, self.lod_count, |p| self.vertex(p)))
+            .collect()
+    }
+
+    // nothing to gain from saving what is quicker to build than to load
+    fn saves_chunks(&self) -> bool {
+        false
+    }
+}
+
+/// Terrain read from an elevation file.
+pub struct HeightmapGenerator {
+    heightmap: Heightmap,
+    chunk_dimensions: ChunkDimensions,
+    lod_count: u32,
+}
+
+impl TerrainGenerator for HeightmapGenerator {
+    fn generate(&mut self, coords: &[ChunkCoord]) -> Vec<RawChunkData> {
+        coords
+            .iter()
+            .map(|coord| self.heightmap.gen_chunk(*coord, &self.chunk_dimensions, self.lod_count))
+            .collect()
+    }
+
+    // a heightmap world is rebuilt from the map itself
+    fn saves_chunks(&self) -> bool {
+        false
+    }
+}
+
+/// Caves and overhangs polygonised in batches by `VoxelPipeline`.
+pub struct VoxelGenerator {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
+    pipeline: VoxelPipeline,
+}
+
+impl VoxelGenerator {
+    pub fn new(device: Arc<wgpu::Device>, queue: Arc<wgpu::Queue>, options: &Options) -> Self {
+        let pipeline = VoxelPipeline::new(&device, options);
+        Self {
+            device,
+            queue,
+            pipeline,
+        }
+    }
+}
+
+impl TerrainGenerator for VoxelGenerator {
+    fn generate(&mut self, coords: &[ChunkCoord]) -> Vec<RawChunkData> {
+        pollster::block_on(self.pipeline.gen_chunks(&self.device, &self.queue, coords))
+    }
+
+    fn max_batch_size(&self) -> usize {
+        self.pipeline.max_batch_size()
+    }
+
+    // saves of the same seed would hold heightfield chunks
+    fn saves_chunks(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::pipelines::load_chunks::RawBufferData;
+
+    /// Holds for every `TerrainGenerator`: one mesh per level of detail, in
+    /// the layout of that level, with the grid covering the chunk.
+    fn assert_generates_chunks(generator: &mut dyn TerrainGenerator, options: &Options) {
+        let coords = [ChunkCoord::new(0, 0), ChunkCoord::new(-3, 5)];
+        let chunks = generator.generate(&coords);
+        assert_eq!(chunks.len(), coords.len());
+
+        let dimensions = options.chunk_dimensions;
+        for (coord, chunk) in coords.iter().zip(&chunks) {
+            assert_eq!(chunk.lods.len() as u32, options.lod_count());
+            for (level, mesh) in chunk.lods.iter().enumerate() {
+                let lod = dimensions.lod(level as u32);
+                assert_eq!(mesh.vertex_data.len() as u64, lod.vertex_buffer_size());
+                assert_eq!(mesh.index_data.len() as u64, lod.index_buffer_size());
+                assert_grid_covers(mesh, &lod, coord.corner(&dimensions));
+            }
+        }
+    }
+
+    fn assert_grid_covers(mesh: &RawBufferData, lod: &ChunkDimensions, corner: Vector2<f32>) {
+        for i in 0..lod.grid_vertices() {
+            let position = mesh.vertex(i).position;
+            let expected = corner + lod.grid_position(i);
+            assert_eq!((position.x, position.z), (expected.x, expected.y), "vertex {}", i);
+        }
+        assert!((0..mesh.num_indices()).all(|i| mesh.index(i) < lod.num_vertices()));
+    }
+
+    #[test]
+    fn flat_terrain_is_level() {
+        let options = Options {
+            terrain: TerrainSource::Flat(7.5),
+            ..Default::default()
+        };
+        let mut generator = FlatGenerator::new(&options, 7.5);
+        assert_generates_chunks(&mut generator, &options);
+
+        let chunk = &generator.generate(&[ChunkCoord::new(2, 2)])[0];
+        assert_eq!(chunk.height_range.1, 7.5);
+        let lowest = 7.5 - options.chunk_dimensions.lod(options.lod_count() - 1).skirt_depth();
+        assert_eq!(chunk.height_range.0, lowest);
+        for i in 0..options.chunk_dimensions.grid_vertices() {
+            let vertex = chunk.lods[0].vertex(i);
+            assert_eq!((vertex.position.y, vertex.normal), (7.5, Vector3::unit_y()));
+        }
+    }
+
+    #[test]
+    fn cpu_noise_keeps_the_contract() {
+        let options = Options {
+            terrain: TerrainSource::CpuNoise,
+            ..Default::default()
+        };
+        assert_generates_chunks(&mut CpuNoiseGenerator::new(&options), &options);
+    }
+}
@@ -0,0 +1,129 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context, Result};
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::lib::options::HeightmapOptions;
+use crate::lib::pipelines::load_chunks::RawChunkData;
+
+use super::mesh::build_chunk;
+use super::noise::{Biome, TerrainVertex};
+use super::{ChunkCoord, ChunkDimensions};
+
+/// Height returned by SRTM tiles where there is no data.
+const HGT_VOID: i16 = i16::MIN;
+
+/// Terrain read from an elevation image instead of generated from noise.
+///
+/// Sample `(0, 0)` sits at the world origin, columns run along +x and rows
+/// along +z. Positions off the edge of the map take the nearest edge height.
+pub struct Heightmap {
+    width: usize,
+    depth: usize,
+    /// Heights in world units, row by row along x.
+    heights: Vec<f32>,
+    /// World units between neighbouring samples.
+    spacing: f32,
+}
+
+impl Heightmap {
+    /// Reads an 8 or 16-bit grayscale PNG, with values mapped to `[0, 1]`, or
+    /// an SRTM `.hgt` tile in metres. Either is then multiplied by the
+    /// vertical scale.
+    pub fn load(options: &HeightmapOptions) -> Result<Self> {
+        let path = &options.path;
+        let is_hgt = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("hgt"));
+        let (width, depth, samples) = if is_hgt {
+            read_hgt(path)
+        } else {
+            read_image(path)
+        }
+        .with_context(|| format!("failed to read heightmap {}", path.display()))?;
+
+        Ok(Self {
+            width,
+            depth,
+            heights: samples
+                .into_iter()
+                .map(|sample| sample * options.vertical_scale)
+                .collect(),
+            spacing: options.horizontal_scale,
+        })
+    }
+
+    fn sample(&self, x: isize, z: isize) -> f32 {
+        let x = x.clamp(0, self.width as isize - 1) as usize;
+        let z = z.clamp(0, self.depth as isize - 1) as usize;
+        self.heights[z * self.width + x]
+    }
+
+    /// Height at a world position, interpolated between samples.
+    pub fn height_at(&self, p: Vector2<f32>) -> f32 {
+        let p = p / self.spacing;
+        let (x, z) = (p.x.floor(), p.y.floor());
+        let (tx, tz) = (p.x - x, p.y - z);
+        let (x, z) = (x as isize, z as isize);
+
+        let top = self.sample(x, z) * (1.0 - tx) + self.sample(x + 1, z) * tx;
+        let bottom = self.sample(x, z + 1) * (1.0 - tx) + self.sample(x + 1, z + 1) * tx;
+        top * (1.0 - tz) + bottom * tz
+    }
+
+    /// Surface and normal at a world position, with the normal taken from the
+    /// slope across neighbouring samples.
+    pub fn vertex(&self, p: Vector2<f32>) -> TerrainVertex {
+        let step = self.spacing;
+        let dx = self.height_at(p + Vector2::new(step, 0.0)) - self.height_at(p - Vector2::new(step, 0.0));
+        let dz = self.height_at(p + Vector2::new(0.0, step)) - self.height_at(p - Vector2::new(0.0, step));
+
+        TerrainVertex {
+            position: Vector3::new(p.x, self.height_at(p), p.y),
+            normal: Vector3::new(-dx, 2.0 * step, -dz).normalize(),
+            // a heightmap says nothing about climate
+            biome: Biome::Plains,
+        }
+    }
+
+    pub fn gen_chunk(&self, coord: ChunkCoord, dimensions: &ChunkDimensions, lod_count: u32) -> RawChunkData {
+        build_chunk(coord, dimensions, lod_count, |p| self.vertex(p))
+    }
+}
+
+fn read_image(path: &Path) -> Result<(usize, usize, Vec<f32>)> {
+    let image = image::open(path)?;
+    let samples = match image.color() {
+        image::ColorType::L8 => image
+            .to_luma8()
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / u8::MAX as f32)
+            .collect(),
+        image::ColorType::L16 => image
+            .to_luma16()
+            .pixels()
+            .map(|pixel| pixel.0[0] as f32 / u16::MAX as f32)
+            .collect(),
+        color => bail!("expected an 8 or 16-bit grayscale image, found {:?}", color),
+    };
+    Ok((image.width() as usize, image.height() as usize, samples))
+}
+
+/// SRTM tiles are square grids of big-endian 16-bit heights in metres, with
+/// no header: 1201x1201 for 3 arc-second data, 3601x3601 for 1 arc-second.
+fn read_hgt(path: &Path) -> Result<(usize, usize, Vec<f32>)> {
+    let bytes = fs::read(path)?;
+    let size = ((bytes.len() / 2) as f64).sqrt() as usize;
+    if size < 2 || size * size * 2 != bytes.len() {
+        bail!("{} bytes is not a square grid of 16-bit samples", bytes.len());
+    }
+
+    let samples = bytes
+        .chunks_exact(2)
+        .map(|sample| match i16::from_be_bytes([sample[0], sample[1]]) {
+            HGT_VOID => 0.0,
+            height => height as f32,
+        })
+        .collect();
+    Ok((size, size, samples))
+}
@@ -0,0 +1,247 @@
+// Triangle table for the marching cubes pass in `gen_terrain.wgsl`, worked
+// out from the faces of the cube rather than written out by hand.
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+/// Corners of a cell as offsets from its lowest corner, the bottom face
+/// first. Mirrored by `CUBE_CORNERS` in `gen_terrain.wgsl`.
+pub const CORNERS: [[u32; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [1, 0, 1],
+    [0, 0, 1],
+    [0, 1, 0],
+    [1, 1, 0],
+    [1, 1, 1],
+    [0, 1, 1],
+];
+
+/// Corners at either end of each edge, lowest first so every cell sharing
+/// an edge puts its vertex in the same place. Mirrored by `CUBE_EDGES`.
+pub const EDGES: [[usize; 2]; 12] = [
+    [0, 1],
+    [1, 2],
+    [3, 2],
+    [0, 3],
+    [4, 5],
+    [5, 6],
+    [7, 6],
+    [4, 7],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+/// Corners around each face.
+const FACES: [[usize; 4]; 6] = [
+    [0, 1, 2, 3],
+    [4, 5, 6, 7],
+    [0, 1, 5, 4],
+    [3, 2, 6, 7],
+    [0, 3, 7, 4],
+    [1, 2, 6, 5],
+];
+
+/// Most triangles the surface through one cell is cut into.
+pub const MAX_TRIANGLES: usize = 5;
+
+/// Marks the end of a case's edges in `packed_table`.
+const END: u32 = 15;
+
+fn corner(i: usize) -> Vector3<f32> {
+    Vector3::from(CORNERS[i]).cast().unwrap()
+}
+
+fn edge_between(a: usize, b: usize) -> usize {
+    EDGES
+        .iter()
+        .position(|edge| *edge == [a, b] || *edge == [b, a])
+        .unwrap()
+}
+
+/// Triangles of the surface through a cell whose solid corners are the set
+/// bits of `case`, as the edges their vertices lie on, three per triangle,
+/// wound counter-clockwise seen from outside the solid.
+pub fn triangulate(case: u8) -> Vec<usize> {
+    let solid = |corner: usize| case & (1 << corner) != 0;
+
+    // the surface crosses each face along lines joining two crossed edges
+    let mut links = Vec::new();
+    for face in FACES {
+        let side = |i: usize| edge_between(face[i], face[(i + 1) % 4]);
+        let crossed: Vec<usize> = (0..4)
+            .filter(|&i| solid(face[i]) != solid(face[(i + 1) % 4]))
+            .collect();
+        match crossed[..] {
+            [a, b] => links.push([side(a), side(b)]),
+            // solid corners diagonally opposite: cut each off on its own, the
+            // same way the cell on the other side of the face does
+            [_, _, _, _] => {
+                for i in (0..4).filter(|&i| solid(face[i])) {
+                    links.push([side((i + 3) % 4), side(i)]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // every crossed edge is on two faces, so the lines join up into loops
+    let mut triangles = Vec::new();
+    while let Some([start, mut next]) = links.pop() {
+        let mut ring = vec![start];
+        while next != start {
+            ring.push(next);
+            let link = links.swap_remove(links.iter().position(|l| l.contains(&next)).unwrap());
+            next = if link[0] == next { link[1] } else { link[0] };
+        }
+
+        // turn the loop to face away from the solid corners it cuts off
+        let midpoint = |edge: usize| (corner(EDGES[edge][0]) + corner(EDGES[edge][1])) * 0.5;
+        let area = (0..ring.len()).fold(Vector3::zero(), |area, i| {
+            area + midpoint(ring[i]).cross(midpoint(ring[(i + 1) % ring.len()]))
+        });
+        let outward = ring.iter().fold(Vector3::zero(), |outward, &edge| {
+            let [a, b] = EDGES[edge];
+            let along = corner(b) - corner(a);
+            if solid(a) {
+                outward + along
+            } else {
+                outward - along
+            }
+        });
+        if area.dot(outward) < 0.0 {
+            ring.reverse();
+        }
+
+        for i in 1..ring.len() - 1 {
+            triangles.extend_from_slice(&[ring[0], ring[i], ring[i + 1]]);
+        }
+    }
+    triangles
+}
+
+/// Every case of `triangulate` packed four bits per edge, sixteen edges to a
+/// case, ending early with 15. Laid out for the `triangle_table` uniform.
+pub fn packed_table() -> [[u32; 2]; 256] {
+    let mut table = [[0; 2]; 256];
+    for (case, packed) in table.iter_mut().enumerate() {
+        let edges = triangulate(case as u8);
+        // leaves at least one slot for the end
+        debug_assert!(edges.len() <= MAX_TRIANGLES * 3);
+        for slot in 0..16 {
+            let edge = edges.get(slot).map_or(END, |edge| *edge as u32);
+            packed[slot / 8] |= edge << (slot % 8 * 4);
+        }
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::options::Options;
+    use crate::lib::pipelines::voxel::VoxelPipeline;
+    use crate::testing::gpu_test;
+    use crate::world::noise::TerrainVertex;
+    use crate::world::{ChunkCoord, ChunkDimensions};
+
+    #[test]
+    fn cuts_every_crossed_edge() {
+        for case in 0..=255u8 {
+            let solid = |corner: usize| case & (1 << corner) != 0;
+            let triangles = triangulate(case);
+            assert!(triangles.len() <= MAX_TRIANGLES * 3, "case {:#010b}", case);
+
+            for (edge, [a, b]) in EDGES.iter().enumerate() {
+                assert_eq!(
+                    triangles.contains(&edge),
+                    solid(*a) != solid(*b),
+                    "case {:#010b} edge {}",
+                    case,
+                    edge,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn faces_away_from_solid_corners() {
+        // one solid corner: the triangle has to face the other seven
+        for solid in 0..8 {
+            let triangles = triangulate(1 << solid);
+            assert_eq!(triangles.len(), 3);
+            let p: Vec<Vector3<f32>> = triangles
+                .iter()
+                .map(|edge| (corner(EDGES[*edge][0]) + corner(EDGES[*edge][1])) * 0.5)
+                .collect();
+            let normal = (p[1] - p[0]).cross(p[2] - p[0]);
+            let centre = Vector3::new(0.5, 0.5, 0.5);
+            assert!(normal.dot(centre - corner(solid)) > 0.0, "corner {}", solid);
+        }
+    }
+
+    #[test]
+    fn packs_every_case() {
+        let table = packed_table();
+        for case in 0..=255u8 {
+            let packed = table[case as usize];
+            let unpacked: Vec<usize> = (0..16)
+                .map(|slot| (packed[slot / 8] >> (slot % 8 * 4)) & 15)
+                .take_while(|edge| *edge != END)
+                .map(|edge| edge as usize)
+                .collect();
+            assert_eq!(unpacked, triangulate(case));
+        }
+    }
+
+    gpu_test! {
+        fn gpu_meshes_face_their_normals(device, queue) {
+            let options = Options {
+                seed: 5,
+                ..Default::default()
+            };
+            let dimensions = options.chunk_dimensions;
+            let coords = [ChunkCoord::new(0, 0), ChunkCoord::new(-7, 4)];
+            let pipeline = VoxelPipeline::new(&device, &options);
+            let chunks = pollster::block_on(pipeline.gen_chunks(&device, &queue, &coords));
+
+            for (coord, chunk) in coords.iter().zip(&chunks) {
+                let corner = coord.corner(&dimensions);
+                let mesh = &chunk.lods[0];
+                let num_vertices = (mesh.vertex_data.len() / ChunkDimensions::VERTEX_SIZE as usize) as u32;
+                assert!(num_vertices > 0 && num_vertices.is_multiple_of(3), "chunk {:?}", coord);
+                assert_eq!(mesh.index_data.len(), num_vertices as usize * 4);
+
+                let mut facing = 0;
+                for triangle in 0..num_vertices / 3 {
+                    let v: Vec<TerrainVertex> = (0..3).map(|i| mesh.vertex(triangle * 3 + i)).collect();
+                    for vertex in &v {
+                        let p = vertex.position;
+                        assert!(
+                            p.x >= corner.x && p.x <= corner.x + dimensions.extent.x
+                                && p.z >= corner.y && p.z <= corner.y + dimensions.extent.y,
+                            "chunk {:?} vertex {:?} outside it",
+                            coord,
+                            p,
+                        );
+                        assert!((vertex.normal.magnitude() - 1.0).abs() < 1e-3);
+                    }
+                    let face = (v[1].position - v[0].position).cross(v[2].position - v[0].position);
+                    if face.dot(v[0].normal + v[1].normal + v[2].normal) > 0.0 {
+                        facing += 1;
+                    }
+                }
+                // sampled normals and the facets between samples differ a little
+                // on sharp bends, but the winding has to agree almost everywhere
+                assert!(
+                    facing as f32 > 0.98 * (num_vertices / 3) as f32,
+                    "chunk {:?}: {} of {} triangles face along their normals",
+                    coord,
+                    facing,
+                    num_vertices / 3,
+                );
+            }
+        }
+    }
+}
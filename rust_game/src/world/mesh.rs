@@ -0,0 +1,86 @@
+use cgmath::Vector2;
+
+use crate::lib::pipelines::load_chunks::{RawBufferData, RawChunkData};
+
+use super::noise::{PackedVertex, TerrainVertex};
+use super::{ChunkCoord, ChunkDimensions};
+
+/// Builds one level of detail of a chunk on the CPU, in the same layout
+/// `gen_terrain.wgsl` writes: grid vertices row by row along x, then the
+/// skirt vertices edge by edge (z min, z max, x min, x max), with the skirt
+/// triangles following the grid triangles.
+///
+/// `surface` gives the terrain vertex above a world position on the xz plane.
+pub fn build_mesh(
+    dimensions: &ChunkDimensions,
+    corner: Vector2<f32>,
+    surface: impl Fn(Vector2<f32>) -> TerrainVertex,
+) -> RawBufferData {
+    let (rx, rz) = (dimensions.resolution.x, dimensions.resolution.y);
+    let row = rx + 1;
+    let grid_vertices = dimensions.grid_vertices();
+
+    let mut vertices: Vec<TerrainVertex> = (0..grid_vertices)
+        .map(|i| surface(corner + dimensions.grid_position(i)))
+        .collect();
+    vertices.reserve(dimensions.skirt_vertices() as usize);
+
+    let mut indices = Vec::with_capacity(dimensions.num_indices() as usize);
+    for quad in 0..rx * rz {
+        let v00 = quad + quad / rx;
+        let v10 = v00 + 1;
+        let v01 = v00 + row;
+        let v11 = v01 + 1;
+        indices.extend_from_slice(&[v00, v01, v11, v00, v11, v10]);
+    }
+
+    // (first grid vertex, step along the edge, segments) per edge
+    let edges = [
+        (0, 1, rx),
+        (rz * row, 1, rx),
+        (0, row, rz),
+        (rx, row, rz),
+    ];
+    for (edge, (start, step, segments)) in edges.into_iter().enumerate() {
+        for i in 0..=segments {
+            let top = start + i * step;
+            let mut vertex = vertices[top as usize];
+            vertex.position.y -= dimensions.skirt_depth();
+            let a_low = vertices.len() as u32;
+            vertices.push(vertex);
+
+            if i == segments {
+                continue;
+            }
+            let (a, b, b_low) = (top, top + step, a_low + 1);
+            // wind each strip so it faces away from the chunk
+            if edge == 0 || edge == 3 {
+                indices.extend_from_slice(&[a, b, b_low, a, b_low, a_low]);
+            } else {
+                indices.extend_from_slice(&[a, b_low, b, a, a_low, b_low]);
+            }
+        }
+    }
+
+    let vertex_data: Vec<PackedVertex> = vertices.into_iter().map(PackedVertex::from).collect();
+
+    RawBufferData {
+        vertex_data: bytemuck::cast_slice(&vertex_data).to_vec(),
+        index_data: bytemuck::cast_slice(&indices).to_vec(),
+    }
+}
+
+/// Builds every level of detail of a chunk on the CPU, most detailed first.
+pub fn build_chunk(
+    coord: ChunkCoord,
+    dimensions: &ChunkDimensions,
+    lod_count: u32,
+    surface: impl Fn(Vector2<f32>) -> TerrainVertex,
+) -> RawChunkData {
+    let corner = coord.corner(dimensions);
+    RawChunkData::new(
+        (0..lod_count)
+            .map(|level| build_mesh(&dimensions.lod(level), corner, &surface))
+            .collect(),
+    )
+}
@@ -1,129 +1,455 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use wgpu::util::DeviceExt;
 
-use crate::lib::model::Mesh;
-use crate::lib::pipelines::load_chunks::{Chunk, RawBufferData};
+use crate::lib::frustum::{Aabb, Frustum};
+use crate::lib::options::Options;
+use crate::lib::pipelines::load_chunks::{Chunk, ChunkMeshes, RawChunkData};
+use crate::lib::pipelines::raycast::RaycastPipeline;
 use crate::lib::create_render_pipeline;
 
+mod arena;
+mod coords;
+pub mod erosion;
+pub mod generator;
+mod heightmap;
+pub mod marching_cubes;
+pub(crate) mod mesh;
+pub mod noise;
+mod queue;
+mod raycast;
+mod region;
+mod sculpt;
+mod surface;
+mod water;
 
-#[repr(C)]
-#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
-struct ChunkData {
-    chunk_size: [u32; 2],
-    chunk_corner: [i32; 2],
-    min_max_height: [f32; 2],
+pub use arena::{ChunkArena, ChunkSlot};
+pub use coords::{ChunkCoord, ChunkDimensions};
+pub use generator::{new_generator, TerrainGenerator};
+pub use heightmap::Heightmap;
+pub use queue::{ChunkQueue, ChunkView};
+pub use raycast::Ray;
+pub use region::RegionStore;
+pub use sculpt::{BrushMode, Sculptor};
+pub use water::WaterPipeline;
+
+/// Chunks being read back from the arena, see `World::read_back_chunks`.
+struct ChunkRead {
+    /// `None` for chunks unloaded or replaced since, whose copy is stale.
+    coords: Vec<Option<ChunkCoord>>,
+    read: arena::ArenaRead,
 }
 
 pub struct World {
-    pub chunks: HashMap<String, Chunk>,
-    pub requested_chunks: HashMap<String, Vec<i32>>,
-    chunk_size: cgmath::Vector2<u32>,
-    pub raw_buffer_data: HashMap<String, RawBufferData>, // raw data coming from compute pipeline
-    pub raw_chunk_data: HashMap<String, RawBufferData>, // raw data, just saved to new location
+    pub chunks: HashMap<ChunkCoord, Chunk>,
+    pub requested_chunks: HashSet<ChunkCoord>,
+    pub chunk_dimensions: ChunkDimensions,
+    render_distance: u32,
+    lod_count: u32,
+    lod_distance: u32,
+    center: ChunkCoord,
+    pub raw_buffer_data: HashMap<ChunkCoord, ChunkMeshes>, // meshes coming from the generation thread
+    pub raw_chunk_data: HashMap<ChunkCoord, RawChunkData>, // CPU copies of loaded chunks, for those that have one
+    edited_chunks: HashSet<ChunkCoord>, // sculpted and not yet handed over to be saved
+    sea_level: f32,
+    heightfield: bool, // chunks are height grids rather than voxel meshes
+    arena: ChunkArena, // vertices and indices of every chunk in `chunks`
+    reads: Vec<ChunkRead>, // GPU-only chunks on their way to `raw_chunk_data`
+    upload_budget: usize, // bytes of `raw_buffer_data` ingested per frame
+    raycaster: RaycastPipeline,
 }
 
 impl World {
-    pub fn new(chunk_size: cgmath::Vector2<u32>) -> Self {
+    pub fn new(device: &wgpu::Device, options: &Options) -> Self {
+        // room for a heightfield at the render distance to begin with
+        let dimensions = options.chunk_dimensions;
+        let lods = (0..options.lod_count()).map(|level| dimensions.lod(level));
+        let (chunk_vertices, chunk_indices) = lods.fold((0, 0), |(vertices, indices), lod| {
+            (vertices + lod.num_vertices(), indices + lod.num_indices())
+        });
+        let radius = options.render_distance as i32;
+        let loaded = ChunkCoord::new(0, 0).within_radius(radius).count() as u32;
+
         Self {
             chunks: HashMap::new(),
-            requested_chunks: HashMap::new(),
-            chunk_size,
+            requested_chunks: HashSet::new(),
+            chunk_dimensions: options.chunk_dimensions,
+            render_distance: options.render_distance,
+            // voxel meshes have no skirts, so they're only made at full detail
+            lod_count: if options.terrain.is_heightfield() { options.lod_count() } else { 1 },
+            lod_distance: options.lod_distance.max(1),
+            center: ChunkCoord::new(0, 0),
             raw_buffer_data: HashMap::new(),
             raw_chunk_data: HashMap::new(),
+            edited_chunks: HashSet::new(),
+            sea_level: options.sea_level,
+            heightfield: options.terrain.is_heightfield(),
+            arena: ChunkArena::new(device, loaded * chunk_vertices, chunk_indices),
+            reads: Vec::new(),
+            upload_budget: options.chunk_upload_bytes,
+            raycaster: RaycastPipeline::new(device),
         }
     }
 
-    pub fn preflight_chunks<'a, 'b>(
+    /// Whether chunks are height grids, see `TerrainSource::is_heightfield`.
+    /// Voxel terrain can't be sculpted, walked on or flooded.
+    pub fn is_heightfield(&self) -> bool {
+        self.heightfield
+    }
+
+    /// Box around a chunk whose vertices span `height_range`.
+    fn chunk_bounds(&self, coord: ChunkCoord, (low, high): (f32, f32)) -> Aabb {
+        let corner = coord.corner(&self.chunk_dimensions);
+        let extent = self.chunk_dimensions.extent;
+        Aabb::new(
+            cgmath::Vector3::new(corner.x, low, corner.y),
+            cgmath::Vector3::new(corner.x + extent.x, high, corner.y + extent.y),
+        )
+    }
+
+    /// Chunk containing the given world position.
+    pub fn chunk_at(&self, position: cgmath::Vector3<f32>) -> ChunkCoord {
+        ChunkCoord::from_world(position.x, position.z, &self.chunk_dimensions)
+    }
+
+    pub fn render_distance(&self) -> u32 {
+        self.render_distance
+    }
+
+    /// Changes the radius of loaded chunks. Chunks outside the new radius are
+    /// released on the next call to `preflight_chunks`.
+    pub fn set_render_distance(&mut self, render_distance: u32) {
+        self.render_distance = render_distance;
+    }
+
+    /// World-space distance from the camera to the edge of the loaded area.
+    pub fn view_distance(&self) -> f32 {
+        let extent = self.chunk_dimensions.extent;
+        self.render_distance as f32 * extent.x.max(extent.y)
+    }
+
+    pub fn preflight_chunks(
         &mut self,
         position: cgmath::Vector3<f32>,
     ) {
-        // define chunk boundaries
-        let r = 10; // chunk distance
-        let n = 2 * r + 1;
-        let mut x: i32;
-        let mut z: i32;
-
-        // Get the x and z coords of the chunk identifier
-        let x_coord =
-            ((position.x as i32 / self.chunk_size.x as i32) + r) * self.chunk_size.x as i32;
-        let z_coord =
-            ((position.z as i32 / self.chunk_size.y as i32) + r) * self.chunk_size.y as i32;
+        let r = self.render_distance as i32;
+        let center = self.chunk_at(position);
+        self.center = center;
 
         let mut new_chunks = HashMap::new();
+        let mut new_requests = HashSet::new();
+
+        for coord in center.within_radius(r) {
+            if let Some(chunk) = self.chunks.remove(&coord) {
+                // generated chunk exists, keep it
+                new_chunks.insert(coord, chunk);
+            } else if !self.raw_buffer_data.contains_key(&coord) {
+                // generated chunk does not exist and is not waiting to be
+                // ingested, request it
+                new_requests.insert(coord);
+            }
+        }
+
+        // drop data for chunks that fell out of range
+        let in_range = |coord: &ChunkCoord| center.is_within_radius(*coord, r);
+        self.raw_chunk_data.retain(|coord, _| in_range(coord));
+        self.raw_buffer_data.retain(|coord, _| in_range(coord));
+
+        for (coord, chunk) in std::mem::replace(&mut self.chunks, new_chunks) {
+            self.arena.remove(&chunk.slot);
+            self.forget_read(coord);
+        }
+        self.requested_chunks = new_requests;
+
+        self.update_lods();
+    }
+
+    /// Requested chunks in the order they should be generated for this view.
+    pub fn generation_queue(&self, view: &ChunkView) -> ChunkQueue {
+        ChunkQueue::new(
+            self.requested_chunks.iter().copied(),
+            view,
+            self.chunk_dimensions,
+        )
+    }
+
+    /// Level of detail for a chunk, dropping one level every `lod_distance`
+    /// chunks away from the camera.
+    fn lod_for(&self, coord: ChunkCoord) -> usize {
+        let distance = (self.center.distance_squared(coord) as f32).sqrt() as u32;
+        (distance / self.lod_distance).min(self.lod_count - 1) as usize
+    }
+
+    /// Picks the level of detail of every loaded chunk, and turns on skirts
+    /// wherever a neighbour is drawn at a different level.
+    fn update_lods(&mut self) {
+        let lods: HashMap<ChunkCoord, usize> = self
+            .chunks
+            .keys()
+            .map(|coord| (*coord, self.lod_for(*coord)))
+            .collect();
+
+        for (coord, chunk) in self.chunks.iter_mut() {
+            let lod = lods[coord];
+            chunk.lod = lod;
+            chunk.skirts = coord
+                .neighbours()
+                .any(|neighbour| lods.get(&neighbour).is_some_and(|l| *l != lod));
+        }
+    }
+
+    /// Takes in the chunks read back since the last frame, then uploads the
+    /// waiting chunks nearest the camera. Both come out of the upload budget,
+    /// and whatever doesn't fit is left for later frames.
+    pub fn ingest_chunk_data(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> ChunkUploadStats {
+        let mut stats = ChunkUploadStats::default();
+        // what is being read back is wanted now, so it goes first
+        self.finish_read_backs(device, &mut stats);
+
+        let mut waiting: Vec<ChunkCoord> = self.raw_buffer_data.keys().copied().collect();
+        waiting.sort_by_key(|coord| self.center.distance_squared(*coord));
+        for coord in waiting {
+            let size = self.raw_buffer_data[&coord].size_bytes();
+            if !self.within_budget(&stats, size) {
+                break;
+            }
+            stats.uploaded += 1;
+            stats.bytes += size;
 
-        for i in 0..n {
-            for j in 0..n {
-                x = i - r;
-                z = j - r;
-
-                // convert anchor point to coordinates
-                let x_anchor = x * self.chunk_size.x as i32 + x_coord - (self.chunk_size.x as i32 * r);
-                let z_anchor = z * self.chunk_size.y as i32 + z_coord - (self.chunk_size.y as i32 * r);
-                let anchor_coords = vec![x_anchor, z_anchor];
-
-                let chunk_key = format!("{}_{}", x_anchor, z_anchor);
-
-                // if chunk is within render distance
-                if x * x + z * z <= r * r + 1 {
-                    if let Some(chunk) = self.chunks.remove(&chunk_key) {
-                        // generated chunk exists, keep it
-                        new_chunks.insert(chunk_key.clone(), chunk);
-                        if let Some(_) = self.requested_chunks.remove(&chunk_key) {
-                            // leave chunk removed from requested list
-                        }
-                    } else {
-                        // generated chunk does not exist, request it
-                        if let Some(coords) = self.requested_chunks.remove(&chunk_key) {
-                            // chunk exists
-                            self.requested_chunks.insert(chunk_key.clone(), coords);
-                        } else {
-                            // chunk does not exist, request
-                            self.requested_chunks.insert(chunk_key.clone(), anchor_coords);
-                        }
-                    }
+            let meshes = self.raw_buffer_data.remove(&coord).unwrap();
+            let height_range = meshes.height_range();
+            let chunk = Chunk {
+                slot: self.arena.insert(device, queue, &meshes),
+                lod: 0,
+                skirts: false,
+                bounds: self.chunk_bounds(coord, height_range),
+            };
+
+            if let Some(replaced) = self.chunks.insert(coord, chunk) {
+                self.arena.remove(&replaced.slot);
+            }
+            self.forget_read(coord);
+            match meshes {
+                ChunkMeshes::Cpu(chunk_data) => {
+                    self.raw_chunk_data.insert(coord, chunk_data);
+                }
+                ChunkMeshes::Gpu(_) => {
+                    self.raw_chunk_data.remove(&coord);
                 }
             }
         }
-  
-        self.chunks = new_chunks;
+        stats.waiting = self.raw_buffer_data.len() as u32;
+
+        if stats.uploaded > 0 {
+            self.update_lods();
+        }
+        stats
     }
 
-    pub fn ingest_chunk_data(&mut self, device: &wgpu::Device) {
-        for (chunk_key, chunk_data) in &self.raw_buffer_data {
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Vertex Buffer"),
-                contents: &chunk_data.vertex_data,
-                usage: wgpu::BufferUsages::VERTEX,
-            });
+    /// Starts reading back the meshes of whichever of `coords` are loaded
+    /// but only on the GPU, all in one copy. They turn up in
+    /// `raw_chunk_data` a frame or so later, once `ingest_chunk_data` finds
+    /// them landed. Returns whether every loaded one is there already.
+    pub fn read_back_chunks(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        coords: impl IntoIterator<Item = ChunkCoord>,
+    ) -> bool {
+        let missing: HashSet<ChunkCoord> = coords
+            .into_iter()
+            .filter(|coord| self.chunks.contains_key(coord) && !self.raw_chunk_data.contains_key(coord))
+            .collect();
+        if missing.is_empty() {
+            return true;
+        }
 
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some("Index Buffer"),
-                contents: &chunk_data.index_data,
-                usage: wgpu::BufferUsages::INDEX,
+        let reading: HashSet<ChunkCoord> = self
+            .reads
+            .iter()
+            .flat_map(|read| read.coords.iter().flatten())
+            .copied()
+            .collect();
+        let coords: Vec<ChunkCoord> = missing.difference(&reading).copied().collect();
+        if !coords.is_empty() {
+            let slots: Vec<&ChunkSlot> = coords.iter().map(|coord| &self.chunks[coord].slot).collect();
+            let read = self.arena.start_read(device, queue, &slots);
+            self.reads.push(ChunkRead {
+                coords: coords.into_iter().map(Some).collect(),
+                read,
             });
+        }
+        false
+    }
 
-            // chunk size x * chunk size y * 6
-            let num_elements = self.chunk_size.x * self.chunk_size.y * 6;
-            let chunk = Chunk {
-                mesh: Mesh {
-                    name: chunk_key.to_string(),
-                    vertex_buffer,
-                    index_buffer,
-                    num_elements,
-                    material: 0,
-                    index_format: wgpu::IndexFormat::Uint32,
-                },
+    /// Whether `size` more bytes fit in this frame's upload budget. The
+    /// first chunk always does, however big, so nothing waits forever.
+    fn within_budget(&self, stats: &ChunkUploadStats, size: usize) -> bool {
+        stats.uploaded + stats.read_back == 0 || stats.bytes + size <= self.upload_budget
+    }
+
+    /// Moves the chunks whose read back has landed into `raw_chunk_data`,
+    /// up to the upload budget, without waiting for the rest.
+    fn finish_read_backs(&mut self, device: &wgpu::Device, stats: &mut ChunkUploadStats) {
+        if self.reads.is_empty() {
+            return;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        let mut i = 0;
+        while i < self.reads.len() {
+            let size = self.reads[i].read.size_bytes();
+            if !self.within_budget(stats, size) {
+                break;
+            }
+            let Some(chunks) = self.reads[i].read.try_finish() else {
+                i += 1;
+                continue;
             };
+            let read = self.reads.remove(i);
+            stats.bytes += size;
+            for (coord, chunk) in read.coords.into_iter().zip(chunks) {
+                // chunks about to be replaced are as stale as unloaded ones
+                if let Some(coord) = coord.filter(|coord| !self.raw_buffer_data.contains_key(coord)) {
+                    stats.read_back += 1;
+                    self.raw_chunk_data.insert(coord, chunk);
+                }
+            }
+        }
+    }
 
-            self.chunks.insert(chunk_key.to_string(), chunk);
-            self.raw_chunk_data.insert(chunk_key.to_string(), chunk_data.clone());
+    /// Drops a chunk from the reads in flight, as what they copied no longer
+    /// matches what is loaded.
+    fn forget_read(&mut self, coord: ChunkCoord) {
+        for read in &mut self.reads {
+            for read_coord in &mut read.coords {
+                if *read_coord == Some(coord) {
+                    *read_coord = None;
+                }
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct FogUniform {
+    color: [f32; 4],
+    start: f32,
+    end: f32,
+    _padding: [u32; 2],
+}
+
+impl FogUniform {
+    pub const COLOR: wgpu::Color = wgpu::Color {
+        r: 0.1,
+        g: 0.2,
+        b: 0.3,
+        a: 1.0,
+    };
+
+    /// Fog that fades terrain into the clear colour over the last stretch
+    /// before `view_distance`.
+    pub fn new(view_distance: f32) -> Self {
+        Self {
+            color: [
+                Self::COLOR.r as f32,
+                Self::COLOR.g as f32,
+                Self::COLOR.b as f32,
+                Self::COLOR.a as f32,
+            ],
+            start: view_distance * 0.6,
+            end: view_distance,
+            _padding: [0; 2],
+        }
+    }
+}
+
+/// How many chunks the last frame drew, and how many it skipped as out of
+/// view.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkDrawStats {
+    pub drawn: u32,
+    pub culled: u32,
+}
+
+/// Chunks uploaded and read back in the last frame, and how many are still
+/// waiting to be uploaded in a later one.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChunkUploadStats {
+    pub uploaded: u32,
+    pub read_back: u32,
+    /// Of both, which share the upload budget.
+    pub bytes: usize,
+    pub waiting: u32,
+}
+
+/// Draws of the chunk meshes in one frame, each as arguments to
+/// `draw_indexed_indirect` over the `ChunkArena`.
+struct ChunkDraws {
+    draws: Vec<wgpu::util::DrawIndexedIndirect>,
+    /// The draws, on the GPU for `multi_draw_indexed_indirect`. `None`
+    /// where the device can't do it, leaving one `draw_indexed` per chunk.
+    buffer: Option<wgpu::Buffer>,
+    /// Draws the buffer has room for.
+    capacity: usize,
+}
+
+impl ChunkDraws {
+    /// Bytes of one set of arguments.
+    const DRAW_SIZE: usize = std::mem::size_of::<wgpu::util::DrawIndexedIndirect>();
+
+    fn new(device: &wgpu::Device) -> Self {
+        let multi_draw = device.features().contains(wgpu::Features::MULTI_DRAW_INDIRECT);
+        Self {
+            draws: Vec::new(),
+            buffer: multi_draw.then(|| Self::create_buffer(device, 1)),
+            capacity: 1,
+        }
+    }
+
+    fn create_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("TerrainPipeline: Draws"),
+            size: (capacity * Self::DRAW_SIZE) as u64,
+            usage: wgpu::BufferUsages::INDIRECT | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Uploads the draws, if they are drawn from a buffer.
+    fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.buffer.is_none() || self.draws.is_empty() {
+            return;
+        }
+        if self.draws.len() > self.capacity {
+            self.capacity = self.draws.len().next_power_of_two();
+            self.buffer = Some(Self::create_buffer(device, self.capacity));
+        }
+        let data: Vec<u8> = self.draws.iter().flat_map(|draw| draw.as_bytes()).copied().collect();
+        queue.write_buffer(self.buffer.as_ref().unwrap(), 0, &data);
+    }
+
+    fn render<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        match &self.buffer {
+            Some(buffer) if !self.draws.is_empty() => {
+                render_pass.multi_draw_indexed_indirect(buffer, 0, self.draws.len() as u32);
+            }
+            Some(_) => {}
+            None => {
+                for draw in &self.draws {
+                    let indices = draw.base_index..draw.base_index + draw.vertex_count;
+                    render_pass.draw_indexed(indices, draw.vertex_offset, 0..1);
+                }
+            }
         }
-        self.raw_buffer_data = HashMap::new();
     }
 }
 
 pub struct WorldPipeline {
     render_pipeline: wgpu::RenderPipeline,
+    fog_buffer: wgpu::Buffer,
+    fog_bind_group: wgpu::BindGroup,
+    water: WaterPipeline,
+    chunk_draws: ChunkDraws,
 }
 
 impl WorldPipeline {
@@ -133,12 +459,41 @@ impl WorldPipeline {
         light_layout: &wgpu::BindGroupLayout,
         color_format: wgpu::TextureFormat,
         depth_format: Option<wgpu::TextureFormat>,
+        view_distance: f32,
+        sea_level: f32,
     ) -> Self {
+        let fog_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("TerrainPipeline: Fog"),
+            contents: bytemuck::bytes_of(&FogUniform::new(view_distance)),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let fog_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("TerrainPipeline::Fog::Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let fog_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("TerrainPipeline: Fog BindGroup"),
+            layout: &fog_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: fog_buffer.as_entire_binding(),
+            }],
+        });
+
         let shader = device.create_shader_module(wgpu::include_wgsl!("terrain.wgsl"));
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("TerrainPipeline::Render::PipelineLayout"),
-                bind_group_layouts: &[camera_layout, light_layout],
+                bind_group_layouts: &[camera_layout, light_layout, &fog_layout],
                 push_constant_ranges: &[],
             });
         let render_pipeline = create_render_pipeline(
@@ -147,7 +502,7 @@ impl WorldPipeline {
             color_format,
             depth_format,
             &[wgpu::VertexBufferLayout {
-                array_stride: 32,
+                array_stride: ChunkDimensions::VERTEX_SIZE as wgpu::BufferAddress,
                 step_mode: wgpu::VertexStepMode::Vertex,
                 attributes: &[
                     wgpu::VertexAttribute {
@@ -160,31 +515,187 @@ impl WorldPipeline {
                         offset: 16,
                         shader_location: 1,
                     },
+                    wgpu::VertexAttribute {
+                        format: wgpu::VertexFormat::Uint32,
+                        offset: 12,
+                        shader_location: 2,
+                    },
                 ],
             }],
             &shader,
+            wgpu::BlendState::REPLACE,
+        );
+        let water = WaterPipeline::new(
+            device,
+            camera_layout,
+            light_layout,
+            &fog_layout,
+            color_format,
+            depth_format,
+            sea_level,
         );
 
         Self {
             render_pipeline,
+            fog_buffer,
+            fog_bind_group,
+            water,
+            chunk_draws: ChunkDraws::new(device),
         }
     }
 
-    pub fn render<'a, 'b>(
+    /// Moves the fog so it ends at the new edge of the loaded area.
+    pub fn set_view_distance(&self, queue: &wgpu::Queue, view_distance: f32) {
+        queue.write_buffer(
+            &self.fog_buffer,
+            0,
+            bytemuck::bytes_of(&FogUniform::new(view_distance)),
+        );
+    }
+
+    pub fn animate_water(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.water.animate(queue, dt);
+    }
+
+    /// Picks the chunks in view and works out their draws. Has to be called
+    /// before `render` every frame, outside the render pass.
+    pub fn prepare(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        terrain: &World,
+        frustum: &Frustum,
+    ) -> ChunkDrawStats {
+        let mut stats = ChunkDrawStats::default();
+        self.chunk_draws.draws.clear();
+        for chunk in terrain.chunks.values() {
+            if !frustum.intersects(&chunk.bounds) {
+                stats.culled += 1;
+                continue;
+            }
+            stats.drawn += 1;
+
+            let mesh = chunk.slot.lods[chunk.lod];
+            // skirts sit after the grid indices, so leaving them off is just a
+            // shorter draw. Voxel meshes have neither.
+            let num_indices = if chunk.skirts || !terrain.is_heightfield() {
+                mesh.num_indices
+            } else {
+                terrain.chunk_dimensions.lod(chunk.lod as u32).grid_indices()
+            };
+            if num_indices == 0 {
+                continue;
+            }
+            self.chunk_draws.draws.push(wgpu::util::DrawIndexedIndirect {
+                vertex_count: num_indices,
+                instance_count: 1,
+                base_index: mesh.first_index,
+                vertex_offset: mesh.base_vertex as i32,
+                base_instance: 0,
+            });
+        }
+        self.chunk_draws.upload(device, queue);
+        stats
+    }
+
+    /// Draws the terrain picked by `prepare`, then the water over it.
+    pub fn render<'a>(
         &'a self,
-        render_pass: &'b mut wgpu::RenderPass<'a>,
+        render_pass: &mut wgpu::RenderPass<'a>,
         terrain: &'a World,
+        frustum: &Frustum,
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     ) {
         render_pass.set_pipeline(&self.render_pipeline);
         render_pass.set_bind_group(0, camera_bind_group, &[]);
         render_pass.set_bind_group(1, light_bind_group, &[]);
-        for (_, chunk) in &terrain.chunks {
-            render_pass
-                .set_index_buffer(chunk.mesh.index_buffer.slice(..), chunk.mesh.index_format);
-            render_pass.set_vertex_buffer(0, chunk.mesh.vertex_buffer.slice(..));
-            render_pass.draw_indexed(0..chunk.mesh.num_elements, 0, 0..1);
+        render_pass.set_bind_group(2, &self.fog_bind_group, &[]);
+        render_pass.set_index_buffer(terrain.arena.index_buffer().slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_vertex_buffer(0, terrain.arena.vertex_buffer().slice(..));
+        self.chunk_draws.render(render_pass);
+
+        self.water.render(
+            render_pass,
+            terrain,
+            frustum,
+            camera_bind_group,
+            light_bind_group,
+            &self.fog_bind_group,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::lib::pipelines::load_chunks::ComputeWorldPipeline;
+    use crate::testing::gpu_test;
+    use crate::world::mesh::build_chunk;
+    use crate::world::noise::terrain_vertex;
+
+    gpu_test! {
+        fn uploads_nearest_chunks_within_budget(device, queue) {
+            let options = Options::default();
+            let chunk = |coord| {
+                build_chunk(coord, &options.chunk_dimensions, options.lod_count(), |p| {
+                    terrain_vertex(p, options.seed)
+                })
+            };
+            let chunk_bytes = chunk(ChunkCoord::new(0, 0)).size_bytes();
+            let mut world = World::new(
+                &device,
+                &Options {
+                    // room for two and a half chunks a frame
+                    chunk_upload_bytes: chunk_bytes * 5 / 2,
+                    ..Default::default()
+                },
+            );
+            for coord in ChunkCoord::new(0, 0).within_radius(1) {
+                world.raw_buffer_data.insert(coord, ChunkMeshes::Cpu(chunk(coord)));
+            }
+
+            let stats = world.ingest_chunk_data(&device, &queue);
+            assert_eq!((stats.uploaded, stats.bytes, stats.waiting), (2, chunk_bytes * 2, 7));
+            // the middle one goes first
+            assert!(world.chunks.contains_key(&ChunkCoord::new(0, 0)));
+
+            // the rest carried over, two a frame
+            for waiting in [5, 3, 1, 0] {
+                assert_eq!(world.ingest_chunk_data(&device, &queue).waiting, waiting);
+            }
+            assert_eq!(world.chunks.len(), 9);
+            assert!(world.raw_buffer_data.is_empty());
+        }
+    }
+
+    gpu_test! {
+        fn reads_chunks_back_without_waiting(device, queue) {
+            let options = Options::default();
+            let (kept, replaced) = (ChunkCoord::new(0, 0), ChunkCoord::new(1, 0));
+            let pipeline = ComputeWorldPipeline::new(&device, &options);
+            let expected = pollster::block_on(pipeline.gen_chunks(&device, &queue, &[kept]));
+            let generated = pollster::block_on(pipeline.gen_gpu_chunks(&device, &queue, &[kept, replaced, replaced]));
+            let mut on_gpu = generated.into_iter().map(|chunk| ChunkMeshes::Gpu(Arc::new(chunk)));
+            let mut world = World::new(&device, &options);
+            world.raw_buffer_data.insert(kept, on_gpu.next().unwrap());
+            world.raw_buffer_data.insert(replaced, on_gpu.next().unwrap());
+            world.ingest_chunk_data(&device, &queue);
+
+            assert!(!world.read_back_chunks(&device, &queue, [kept, replaced]));
+            assert!(world.raw_chunk_data.is_empty());
+            // loaded again while its copy was on the way, which is then stale
+            world.raw_buffer_data.insert(replaced, on_gpu.next().unwrap());
+            device.poll(wgpu::Maintain::Wait);
+            let stats = world.ingest_chunk_data(&device, &queue);
+            assert_eq!((stats.uploaded, stats.read_back), (1, 1));
+
+            assert_eq!(world.raw_chunk_data[&kept].lods[0].vertex_data, expected[0].lods[0].vertex_data);
+            assert!(!world.raw_chunk_data.contains_key(&replaced));
+            assert!(world.read_back_chunks(&device, &queue, [kept]));
+            assert!(!world.read_back_chunks(&device, &queue, [replaced]));
         }
     }
 }
@@ -0,0 +1,329 @@
+// CPU copy of the terrain noise in `gen_terrain.wgsl`, for generating and
+// querying terrain without a GPU. Every function mirrors the WGSL function of
+// the same name and has to be kept in step with it.
+
+use cgmath::{ElementWise, InnerSpace, Matrix2, Vector2, Vector3, Vector4};
+
+/// Biome ids as stored in the vertex padding, matching the `BIOME_*`
+/// constants in the shaders.
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Biome {
+    Plains = 0,
+    Mountains = 1,
+    Desert = 2,
+    Snow = 3,
+    Ocean = 4,
+}
+
+impl Biome {
+    /// Unknown ids read as plains.
+    pub fn from_id(id: u32) -> Self {
+        match id {
+            1 => Self::Mountains,
+            2 => Self::Desert,
+            3 => Self::Snow,
+            4 => Self::Ocean,
+            _ => Self::Plains,
+        }
+    }
+}
+
+/// A terrain vertex as generated by the compute shader.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TerrainVertex {
+    pub position: Vector3<f32>,
+    pub normal: Vector3<f32>,
+    pub biome: Biome,
+}
+
+/// `TerrainVertex` laid out as in the vertex buffers, with the biome id in
+/// the padding after the position.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PackedVertex {
+    position: [f32; 3],
+    biome: u32,
+    normal: [f32; 3],
+    _padding: u32,
+}
+
+impl From<TerrainVertex> for PackedVertex {
+    fn from(v: TerrainVertex) -> Self {
+        Self {
+            position: v.position.into(),
+            biome: v.biome as u32,
+            normal: v.normal.into(),
+            _padding: 0,
+        }
+    }
+}
+
+impl From<PackedVertex> for TerrainVertex {
+    fn from(v: PackedVertex) -> Self {
+        Self {
+            position: v.position.into(),
+            normal: v.normal.into(),
+            biome: Biome::from_id(v.biome),
+        }
+    }
+}
+
+fn permute3(x: Vector3<f32>) -> Vector3<f32> {
+    x.map(|x| ((x * 34.0 + 1.0) * x) % 289.0)
+}
+
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+/// 2D simplex noise, roughly in `[-1, 1]`.
+// constants are written exactly as in the shader
+#[allow(clippy::excessive_precision)]
+pub fn snoise2(v: Vector2<f32>) -> f32 {
+    const C: Vector4<f32> = Vector4::new(
+        0.211324865405187,
+        0.366025403784439,
+        -0.577350269189626,
+        0.024390243902439,
+    );
+    let mut i = (v + Vector2::new(1.0, 1.0) * v.dot(Vector2::new(C.y, C.y))).map(f32::floor);
+    let x0 = v - i + Vector2::new(1.0, 1.0) * i.dot(Vector2::new(C.x, C.x));
+    // flipped from > to < like the shader
+    let i1 = if x0.x < x0.y {
+        Vector2::new(0.0, 1.0)
+    } else {
+        Vector2::new(1.0, 0.0)
+    };
+    let x12 = Vector4::new(x0.x + C.x - i1.x, x0.y + C.x - i1.y, x0.x + C.z, x0.y + C.z);
+    i = i.map(|i| i % 289.0);
+    let p = permute3(
+        permute3(Vector3::new(0.0, i1.y, 1.0).add_element_wise(i.y))
+            .add_element_wise(i.x)
+            + Vector3::new(0.0, i1.x, 1.0),
+    );
+    let x12_xy = Vector2::new(x12.x, x12.y);
+    let x12_zw = Vector2::new(x12.z, x12.w);
+    let mut m = Vector3::new(x0.dot(x0), x12_xy.dot(x12_xy), x12_zw.dot(x12_zw))
+        .map(|d| (0.5 - d).max(0.0));
+    m = m.mul_element_wise(m);
+    m = m.mul_element_wise(m);
+    let x = (p * C.w).map(|p| 2.0 * fract(p) - 1.0);
+    let h = x.map(|x| x.abs() - 0.5);
+    let ox = x.map(|x| (x + 0.5).floor());
+    let a0 = x - ox;
+    m = m.mul_element_wise(
+        (a0.mul_element_wise(a0) + h.mul_element_wise(h))
+            .map(|d| 1.79284291400159 - 0.85373472095314 * d),
+    );
+    let g = Vector3::new(
+        a0.x * x0.x + h.x * x0.y,
+        a0.y * x12.x + h.y * x12.y,
+        a0.z * x12.z + h.z * x12.w,
+    );
+    130.0 * m.dot(g)
+}
+
+/// PCG hash, from "Hash Functions for GPU Rendering" (Jarzynski & Olano, 2020)
+fn pcg(v: u32) -> u32 {
+    let state = v.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Five octaves of simplex noise, offset and rotated per seed.
+pub fn fbm(p: Vector2<f32>, seed: u32) -> f32 {
+    const NUM_OCTAVES: u32 = 5;
+    let h = pcg(seed);
+    let seed_shift = Vector2::new((h & 0xffff) as f32, (h >> 16) as f32) / 65536.0 * 289.0;
+    let angle = 0.5 + (pcg(h) & 0xffff) as f32 / 65536.0 * std::f32::consts::TAU;
+    let mut x = p * 0.01 + seed_shift;
+    let mut v = 0.0;
+    let mut a = 0.5;
+    let shift = Vector2::new(100.0, 100.0);
+    let (sin, cos) = angle.sin_cos();
+    let rot = Matrix2::new(cos, sin, -sin, cos);
+
+    for _ in 0..NUM_OCTAVES {
+        v += a * snoise2(x);
+        x = rot * x * 2.0 + shift;
+        a *= 0.5;
+    }
+
+    v
+}
+
+/// Temperature and moisture in `[0, 1]`, changing over hundreds of units
+/// rather than tens like the terrain itself.
+pub fn climate(p: Vector2<f32>, seed: u32) -> Vector2<f32> {
+    let h = pcg(seed ^ 0x9e3779b9);
+    let seed_shift = Vector2::new((h & 0xffff) as f32, (h >> 16) as f32) / 65536.0 * 289.0;
+    let x = p * 0.002 + seed_shift;
+    let temperature = snoise2(x) + 0.5 * snoise2(x * 2.0 + Vector2::new(31.0, 17.0));
+    let moisture = snoise2(x + Vector2::new(113.0, 57.0)) + 0.5 * snoise2(x * 2.0 + Vector2::new(71.0, 5.0));
+    Vector2::new(temperature, moisture).map(|c| (c * 0.5 + 0.5).clamp(0.0, 1.0))
+}
+
+/// Biome at a corner of the climate table: temperature cold, temperate or
+/// hot by moisture dry, average or wet.
+fn climate_biome(cell: Vector2<u32>) -> Biome {
+    match cell.x * 3 + cell.y {
+        0 | 1 => Biome::Snow,
+        3 => Biome::Mountains,
+        4 | 7 => Biome::Plains,
+        6 => Biome::Desert,
+        _ => Biome::Ocean,
+    }
+}
+
+/// Lowest and highest terrain the noise maps to in a biome.
+pub fn biome_heights(biome: Biome) -> Vector2<f32> {
+    match biome {
+        Biome::Plains => Vector2::new(-2.0, 6.0),
+        Biome::Mountains => Vector2::new(0.0, 40.0),
+        Biome::Desert => Vector2::new(-1.0, 8.0),
+        Biome::Snow => Vector2::new(2.0, 25.0),
+        Biome::Ocean => Vector2::new(-25.0, -5.0),
+    }
+}
+
+/// Biome of the nearest corner of the climate table.
+pub fn biome(climate: Vector2<f32>) -> Biome {
+    // WGSL rounds halves to even
+    climate_biome((climate * 2.0).map(|c| c.round_ties_even() as u32))
+}
+
+/// Height range blended between the four surrounding corners of the climate
+/// table, so the terrain never steps where the biome changes.
+fn terrain_heights(climate: Vector2<f32>) -> Vector2<f32> {
+    let g = climate * 2.0;
+    let cell = g.map(|g| (g as u32).min(1));
+    let t = g - cell.cast::<f32>().unwrap();
+    let h = |dx, dz| biome_heights(climate_biome(cell + Vector2::new(dx, dz)));
+    let top = h(0, 0) * (1.0 - t.x) + h(1, 0) * t.x;
+    let bottom = h(0, 1) * (1.0 - t.x) + h(1, 1) * t.x;
+    top * (1.0 - t.y) + bottom * t.y
+}
+
+/// Terrain surface above the point `p` on the xz plane.
+pub fn terrain_point(p: Vector2<f32>, seed: u32) -> Vector3<f32> {
+    let heights = terrain_heights(climate(p, seed));
+    // fbm is centred on zero, the middle of the range should be too
+    let t = fbm(p, seed) + 0.5;
+    Vector3::new(
+        p.x,
+        heights.x * (1.0 - t) + heights.y * t,
+        p.y,
+    )
+}
+
+/// Terrain surface, its normal and its biome above the point `p` on the xz
+/// plane.
+pub fn terrain_vertex(p: Vector2<f32>, seed: u32) -> TerrainVertex {
+    let v = terrain_point(p, seed);
+
+    let tpx = terrain_point(p + Vector2::new(0.1, 0.0), seed) - v;
+    let tpz = terrain_point(p + Vector2::new(0.0, 0.1), seed) - v;
+    let tnx = terrain_point(p + Vector2::new(-0.1, 0.0), seed) - v;
+    let tnz = terrain_point(p + Vector2::new(0.0, -0.1), seed) - v;
+
+    let pn = tpz.cross(tpx).normalize();
+    let nn = tnz.cross(tnx).normalize();
+
+    TerrainVertex {
+        position: v,
+        normal: (pn + nn) * 0.5,
+        biome: biome(climate(p, seed)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::options::Options;
+    use crate::lib::pipelines::load_chunks::ComputeWorldPipeline;
+    use crate::testing::gpu_test;
+    use crate::world::ChunkCoord;
+
+    const POSITION_TOLERANCE: f32 = 2e-3;
+    const NORMAL_TOLERANCE: f32 = 1e-2;
+
+    /// Grid vertices of the most detailed level of a chunk from the GPU.
+    fn gpu_vertices(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        options: &Options,
+        coord: ChunkCoord,
+    ) -> Vec<TerrainVertex> {
+        let pipeline = ComputeWorldPipeline::new(device, options);
+        let chunks = pollster::block_on(pipeline.gen_chunks(device, queue, &[coord]));
+        (0..options.chunk_dimensions.grid_vertices())
+            .map(|i| chunks[0].lods[0].vertex(i))
+            .collect()
+    }
+
+    fn assert_matches_gpu(device: &wgpu::Device, queue: &wgpu::Queue, seed: u32, coord: ChunkCoord) {
+        let options = Options {
+            seed,
+            ..Default::default()
+        };
+        let dimensions = options.chunk_dimensions;
+
+        for (i, gpu) in gpu_vertices(device, queue, &options, coord).iter().enumerate() {
+            let p = coord.corner(&dimensions) + dimensions.grid_position(i as u32);
+            let cpu = terrain_vertex(p, seed);
+
+            assert!(
+                (cpu.position - gpu.position).magnitude() < POSITION_TOLERANCE,
+                "vertex {} position: cpu {:?}, gpu {:?}",
+                i,
+                cpu.position,
+                gpu.position,
+            );
+            assert!(
+                (cpu.normal - gpu.normal).magnitude() < NORMAL_TOLERANCE,
+                "vertex {} normal: cpu {:?}, gpu {:?}",
+                i,
+                cpu.normal,
+                gpu.normal,
+            );
+            assert_eq!(cpu.biome, gpu.biome, "vertex {} biome", i);
+        }
+    }
+
+    gpu_test! {
+        fn matches_gpu_at_origin(device, queue) {
+            assert_matches_gpu(&device, &queue, 0, ChunkCoord::new(0, 0));
+        }
+    }
+
+    gpu_test! {
+        fn matches_gpu_with_seed(device, queue) {
+            assert_matches_gpu(&device, &queue, 1234, ChunkCoord::new(-3, 7));
+        }
+    }
+
+    gpu_test! {
+        fn matches_gpu_far_from_origin(device, queue) {
+            assert_matches_gpu(&device, &queue, 42, ChunkCoord::new(150, -90));
+        }
+    }
+
+    #[test]
+    fn every_biome_occurs() {
+        let found: std::collections::HashSet<Biome> = (-50..50)
+            .flat_map(|z| (-50..50).map(move |x| Vector2::new(x as f32, z as f32) * 100.0))
+            .map(|p| biome(climate(p, 0)))
+            .collect();
+        for expected in [Biome::Plains, Biome::Mountains, Biome::Desert, Biome::Snow, Biome::Ocean] {
+            assert!(found.contains(&expected), "no {:?} within 5000 units of the origin", expected);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_terrain() {
+        let p = Vector2::new(123.4, -56.7);
+        assert_eq!(fbm(p, 5), fbm(p, 5));
+        assert_ne!(fbm(p, 5), fbm(p, 6));
+    }
+}
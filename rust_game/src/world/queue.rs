@@ -0,0 +1,123 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use cgmath::{InnerSpace, Vector2};
+
+use super::{ChunkCoord, ChunkDimensions};
+
+/// Where the camera is and which way it looks, flattened onto the ground plane.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkView {
+    pub position: Vector2<f32>,
+    /// Normalised look direction on the xz plane.
+    pub forward: Vector2<f32>,
+    /// Cosine of half the horizontal field of view.
+    pub cos_half_fov: f32,
+}
+
+impl ChunkView {
+    pub fn new(position: Vector2<f32>, forward: Vector2<f32>, half_fov: cgmath::Rad<f32>) -> Self {
+        let forward = if forward.magnitude2() > f32::EPSILON {
+            forward.normalize()
+        } else {
+            Vector2::new(1.0, 0.0)
+        };
+        Self {
+            position,
+            forward,
+            cos_half_fov: half_fov.0.cos(),
+        }
+    }
+
+    /// Whether any part of the chunk could be on screen. Chunks close enough
+    /// to touch the camera always count as visible.
+    fn sees(&self, coord: ChunkCoord, dimensions: &ChunkDimensions) -> bool {
+        let to_chunk = coord.center(dimensions) - self.position;
+        let distance = to_chunk.magnitude();
+        let radius = dimensions.extent.magnitude() * 0.5;
+        if distance <= radius {
+            return true;
+        }
+
+        // widen the cone by the angle the chunk itself covers
+        let half_fov = self.cos_half_fov.acos() + (radius / distance).asin();
+        if half_fov >= std::f32::consts::PI {
+            return true;
+        }
+        to_chunk.dot(self.forward) / distance >= half_fov.cos()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct QueuedChunk {
+    coord: ChunkCoord,
+    in_view: bool,
+    distance: f32,
+}
+
+// Greater means generated sooner: visible before hidden, then nearest first.
+impl Ord for QueuedChunk {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.in_view
+            .cmp(&other.in_view)
+            .then_with(|| other.distance.total_cmp(&self.distance))
+            .then_with(|| other.coord.cmp(&self.coord))
+    }
+}
+
+impl PartialOrd for QueuedChunk {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedChunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedChunk {}
+
+/// Requested chunks ordered so the area in front of the camera fills in first.
+pub struct ChunkQueue {
+    heap: BinaryHeap<QueuedChunk>,
+    dimensions: ChunkDimensions,
+}
+
+impl ChunkQueue {
+    pub fn new(
+        coords: impl IntoIterator<Item = ChunkCoord>,
+        view: &ChunkView,
+        dimensions: ChunkDimensions,
+    ) -> Self {
+        let mut queue = Self {
+            heap: BinaryHeap::new(),
+            dimensions,
+        };
+        for coord in coords {
+            queue.heap.push(queue.queued(coord, view));
+        }
+        queue
+    }
+
+    fn queued(&self, coord: ChunkCoord, view: &ChunkView) -> QueuedChunk {
+        QueuedChunk {
+            coord,
+            in_view: view.sees(coord, &self.dimensions),
+            distance: (coord.center(&self.dimensions) - view.position).magnitude(),
+        }
+    }
+}
+
+impl Iterator for ChunkQueue {
+    type Item = ChunkCoord;
+
+    fn next(&mut self) -> Option<ChunkCoord> {
+        self.heap.pop().map(|queued| queued.coord)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.heap.len(), Some(self.heap.len()))
+    }
+}
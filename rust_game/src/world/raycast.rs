@@ -0,0 +1,322 @@
+use std::ops::Range;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::lib::pipelines::load_chunks::RawBufferData;
+use crate::lib::pipelines::raycast::{GpuHit, RaycastPipeline, RaySegment};
+
+use super::{ChunkCoord, World};
+
+/// A ray cast into the terrain, reaching `max_distance` along `direction`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    /// Normalized.
+    pub direction: Vector3<f32>,
+    /// Finite, as the chunks along the ray are walked up to it.
+    pub max_distance: f32,
+}
+
+impl Ray {
+    pub fn new(origin: Vector3<f32>, direction: Vector3<f32>, max_distance: f32) -> Self {
+        assert!(
+            max_distance.is_finite() && max_distance >= 0.0,
+            "ray reach {} isn't a finite distance",
+            max_distance,
+        );
+        Self {
+            origin,
+            direction: direction.normalize(),
+            max_distance,
+        }
+    }
+
+    /// Point `distance` along the ray.
+    pub fn at(&self, distance: f32) -> Vector3<f32> {
+        self.origin + self.direction * distance
+    }
+
+    /// Whether the ray is anywhere between heights `low` and `high` over
+    /// the given distances along it.
+    fn crosses_heights(&self, span: &Range<f32>, (low, high): (f32, f32)) -> bool {
+        let (start, end) = (self.at(span.start).y, self.at(span.end).y);
+        start.min(end) <= high && start.max(end) >= low
+    }
+}
+
+/// Where a ray first meets the terrain.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RayHit {
+    pub position: Vector3<f32>,
+    /// Blended from the corners of the triangle hit, the way the terrain is
+    /// shaded.
+    pub normal: Vector3<f32>,
+    /// Along the ray from its origin.
+    pub distance: f32,
+    pub chunk: ChunkCoord,
+}
+
+impl World {
+    /// Chunks a ray crosses within its reach, loaded or not, in the order
+    /// it crosses them, each with the distances along the ray where it
+    /// enters and leaves. Steps from one chunk border to the next, so a
+    /// long ray costs one entry per chunk rather than per grid cell.
+    pub fn ray_chunks(&self, ray: &Ray) -> Vec<(ChunkCoord, Range<f32>)> {
+        let mut coord = self.chunk_at(ray.origin);
+        let corner = coord.corner(&self.chunk_dimensions);
+        let extent = self.chunk_dimensions.extent;
+        // (chunks to step, distance to the first border, distance between
+        // borders) along one axis
+        let axis = |origin: f32, direction: f32, corner: f32, extent: f32| {
+            if direction > 0.0 {
+                (1, (corner + extent - origin) / direction, extent / direction)
+            } else if direction < 0.0 {
+                (-1, (corner - origin) / direction, -extent / direction)
+            } else {
+                (0, f32::INFINITY, f32::INFINITY)
+            }
+        };
+        let (step_x, mut border_x, delta_x) = axis(ray.origin.x, ray.direction.x, corner.x, extent.x);
+        let (step_z, mut border_z, delta_z) = axis(ray.origin.z, ray.direction.z, corner.y, extent.y);
+
+        let mut chunks = Vec::new();
+        let mut entered = 0.0;
+        loop {
+            let left = border_x.min(border_z).min(ray.max_distance);
+            chunks.push((coord, entered..left));
+            if left >= ray.max_distance {
+                return chunks;
+            }
+            if border_x <= border_z {
+                coord = coord.offset(step_x, 0);
+                border_x += delta_x;
+            } else {
+                coord = coord.offset(0, step_z);
+                border_z += delta_z;
+            }
+            entered = left;
+        }
+    }
+
+    /// First hit of a ray on the chunks in `raw_chunk_data`, on the
+    /// triangles of their most detailed level. Chunks the ray crosses
+    /// without a CPU copy are passed through, so read them back first where
+    /// that matters; `raycast_batch` sees every loaded chunk.
+    pub fn raycast(&self, ray: &Ray) -> Option<RayHit> {
+        // a chunk's triangles stay within its borders, so the first chunk
+        // hit has the nearest hit
+        self.ray_chunks(ray).into_iter().find_map(|(coord, span)| {
+            let chunk = self.raw_chunk_data.get(&coord)?;
+            if !ray.crosses_heights(&span, chunk.height_range) {
+                return None;
+            }
+            let mesh = &chunk.lods[0];
+            let (distance, normal) = (0..self.surface_indices(mesh.num_indices()) / 3)
+                .filter_map(|triangle| intersect_triangle(ray, mesh, triangle))
+                .min_by(|a, b| a.0.total_cmp(&b.0))?;
+            Some(RayHit {
+                position: ray.at(distance),
+                normal,
+                distance,
+                chunk: coord,
+            })
+        })
+    }
+
+    /// First hit of each of a batch of rays, cast on the GPU against every
+    /// chunk in the arena, with or without a CPU copy. Waits for the GPU.
+    #[allow(dead_code)]
+    pub fn raycast_batch(&self, device: &wgpu::Device, queue: &wgpu::Queue, rays: &[Ray]) -> Vec<Option<RayHit>> {
+        if rays.is_empty() {
+            return Vec::new();
+        }
+
+        let mut segments = Vec::new();
+        let mut segment_chunks = Vec::new();
+        for (i, ray) in rays.iter().enumerate() {
+            for (coord, span) in self.ray_chunks(ray) {
+                let Some(chunk) = self.chunks.get(&coord) else {
+                    continue;
+                };
+                if !ray.crosses_heights(&span, (chunk.bounds.min.y, chunk.bounds.max.y)) {
+                    continue;
+                }
+                let mesh = chunk.slot.lods[0];
+                segments.push(RaySegment {
+                    origin: ray.origin.into(),
+                    max_distance: ray.max_distance,
+                    direction: ray.direction.into(),
+                    ray: i as u32,
+                    base_vertex: mesh.base_vertex,
+                    first_index: mesh.first_index,
+                    num_indices: self.surface_indices(mesh.num_indices),
+                    _padding: 0,
+                });
+                segment_chunks.push(coord);
+            }
+        }
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("World::raycast_batch"),
+        });
+        let hits = self.raycaster.encode(
+            device,
+            &mut encoder,
+            rays.len(),
+            &segments,
+            self.arena.vertex_buffer(),
+            self.arena.index_buffer(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        pollster::block_on(RaycastPipeline::read(device, queue, &hits))
+            .into_iter()
+            .map(|hit| {
+                (hit.segment != GpuHit::MISS).then(|| RayHit {
+                    position: hit.position.into(),
+                    normal: hit.normal.into(),
+                    distance: hit.distance,
+                    chunk: segment_chunks[hit.segment as usize],
+                })
+            })
+            .collect()
+    }
+
+    /// Indices a ray can hit out of the `num_indices` of a chunk's most
+    /// detailed mesh, leaving out the skirts of a heightfield.
+    fn surface_indices(&self, num_indices: u32) -> u32 {
+        if self.heightfield {
+            self.chunk_dimensions.grid_indices()
+        } else {
+            num_indices
+        }
+    }
+}
+
+/// Distance along a ray to one triangle of a mesh, within reach, and the
+/// normal there. Hits either side, as `raycast.wgsl` does.
+fn intersect_triangle(ray: &Ray, mesh: &RawBufferData, triangle: u32) -> Option<(f32, Vector3<f32>)> {
+    let corners = [0, 1, 2].map(|i| mesh.vertex(mesh.index(triangle * 3 + i)));
+    let [v0, v1, v2] = corners.map(|corner| corner.position);
+
+    // Möller–Trumbore
+    let (edge1, edge2) = (v1 - v0, v2 - v0);
+    let h = ray.direction.cross(edge2);
+    let a = edge1.dot(h);
+    if a.abs() < 1e-8 {
+        return None; // parallel to the triangle
+    }
+    let f = 1.0 / a;
+    let s = ray.origin - v0;
+    let u = f * s.dot(h);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = f * ray.direction.dot(q);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = f * edge2.dot(q);
+    if !(0.0..=ray.max_distance).contains(&distance) {
+        return None;
+    }
+
+    let normal = corners[0].normal * (1.0 - u - v) + corners[1].normal * u + corners[2].normal * v;
+    Some((distance, normal.normalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lib::options::Options;
+    use crate::testing::{gpu_test, loaded_world};
+    use crate::world::noise::{terrain_vertex, Biome, TerrainVertex};
+
+    #[test]
+    #[should_panic(expected = "isn't a finite distance")]
+    fn needs_a_finite_reach() {
+        Ray::new(Vector3::new(0.0, 0.0, 0.0), Vector3::new(1.0, 0.0, 0.0), f32::INFINITY);
+    }
+
+    gpu_test! {
+        fn walks_every_chunk_the_ray_crosses(device, _) {
+            let world = World::new(&device, &Options::default());
+            let ray = Ray::new(Vector3::new(10.0, 50.0, -3.0), Vector3::new(-2.0, -0.3, 5.0), 200.0);
+
+            let chunks = world.ray_chunks(&ray);
+            assert_eq!(chunks[0], (ChunkCoord::new(0, -1), 0.0..chunks[0].1.end));
+            assert_eq!(chunks.last().unwrap().1.end, ray.max_distance);
+            for pair in chunks.windows(2) {
+                let ((from, left), (to, entered)) = (&pair[0], &pair[1]);
+                assert_eq!(left.end, entered.start);
+                assert_eq!((from.x - to.x).abs() + (from.z - to.z).abs(), 1);
+            }
+            // every point along the ray is in the chunk it is said to be in
+            for (coord, span) in &chunks {
+                let middle = ray.at((span.start + span.end) * 0.5);
+                assert_eq!(world.chunk_at(middle), *coord);
+            }
+        }
+    }
+
+    gpu_test! {
+        fn hits_a_slope_across_chunks(device, queue) {
+            // y = 0.5x + 2
+            let normal = Vector3::new(-0.5, 1.0, 0.0).normalize();
+            let world = loaded_world(&device, &queue, &Options::default(), |p| TerrainVertex {
+                position: Vector3::new(p.x, 0.5 * p.x + 2.0, p.y),
+                normal,
+                biome: Biome::Plains,
+            });
+
+            // starts over the middle chunk and comes down in the one to the east
+            let ray = Ray::new(Vector3::new(10.0, 40.0, 5.0), Vector3::new(1.0, -1.0, 0.2), 100.0);
+            let hit = world.raycast(&ray).unwrap();
+            // 40 - t = 0.5 * (10 + t) + 2 along x, where t is the x travelled
+            let x = 10.0 + 33.0 / 1.5;
+            assert!((hit.position - Vector3::new(x, 0.5 * x + 2.0, 5.0 + 0.2 * (x - 10.0))).magnitude() < 1e-3);
+            assert!((hit.distance - (hit.position - ray.origin).magnitude()).abs() < 1e-3);
+            assert!((hit.normal - normal).magnitude() < 1e-5);
+            assert_eq!(hit.chunk, ChunkCoord::new(1, 0));
+
+            // out of reach, looking away, and leaving the loaded chunks
+            assert_eq!(world.raycast(&Ray { max_distance: 30.0, ..ray }), None);
+            assert_eq!(world.raycast(&Ray::new(ray.origin, Vector3::new(0.0, 1.0, 0.0), 100.0)), None);
+            let away = Ray::new(Vector3::new(40.0, 60.0, 0.0), Vector3::new(1.0, 0.0, 0.0), 100.0);
+            assert_eq!(world.raycast(&away), None);
+        }
+    }
+
+    gpu_test! {
+        fn casts_batches_on_the_gpu(device, queue) {
+            let seed = Options::default().seed;
+            let world = loaded_world(&device, &queue, &Options::default(), |p| terrain_vertex(p, seed));
+
+            // fanning out and down from above the ground, plus one straight up
+            let origin = Vector3::new(3.0, world.height_at(3.0, -7.0).unwrap() + 20.0, -7.0);
+            let rays: Vec<Ray> = (0..16)
+                .map(|i| {
+                    let angle = i as f32 * std::f32::consts::TAU / 16.0;
+                    let direction = Vector3::new(angle.cos(), -0.5, angle.sin());
+                    Ray::new(origin, direction, 400.0)
+                })
+                .chain([Ray::new(origin, Vector3::new(0.0, 1.0, 0.0), 1000.0)])
+                .collect();
+
+            let hits = world.raycast_batch(&device, &queue, &rays);
+            assert_eq!(hits.len(), rays.len());
+            assert!(hits.iter().any(Option::is_some));
+            for (ray, hit) in rays.iter().zip(hits) {
+                let expected = world.raycast(ray);
+                assert_eq!(hit.is_some(), expected.is_some(), "{:?}", ray);
+                if let (Some(hit), Some(expected)) = (hit, expected) {
+                    assert_eq!(hit.chunk, expected.chunk);
+                    assert!((hit.distance - expected.distance).abs() < 1e-2);
+                    assert!((hit.position - expected.position).magnitude() < 1e-2);
+                    assert!((hit.normal - expected.normal).magnitude() < 1e-3);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,456 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::lib::options::ErosionOptions;
+use crate::lib::pipelines::load_chunks::{RawBufferData, RawChunkData};
+
+use super::{ChunkCoord, ChunkDimensions};
+
+/// Chunks along each side of a region file.
+pub const REGION_SIZE: i32 = 16;
+
+const MAGIC: [u8; 4] = *b"TRRN";
+const VERSION: u32 = 3;
+const TABLE_LEN: usize = (REGION_SIZE * REGION_SIZE) as usize;
+const TABLE_START: u64 = size_of::<RegionHeader>() as u64;
+const DATA_START: u64 = TABLE_START + (TABLE_LEN * size_of::<TableEntry>()) as u64;
+
+/// Start of every region file. A region whose header doesn't match the
+/// current world settings is ignored and regenerated.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, bytemuck::Pod, bytemuck::Zeroable)]
+struct RegionHeader {
+    magic: [u8; 4],
+    version: u32,
+    seed: u64,
+    resolution: [u32; 2],
+    extent: [f32; 2],
+    lod_count: u32,
+    region_size: u32,
+    /// All zero when the terrain isn't eroded.
+    erosion: ErosionOptions,
+}
+
+/// Where a chunk's compressed data lives in the region file. A zero length
+/// means the chunk hasn't been saved.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, bytemuck::Pod, bytemuck::Zeroable)]
+struct TableEntry {
+    offset: u32,
+    length: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct RegionCoord {
+    x: i32,
+    z: i32,
+}
+
+impl RegionCoord {
+    /// Region containing the chunk, and the chunk's slot in its table.
+    fn of(chunk: ChunkCoord) -> (Self, usize) {
+        let region = Self {
+            x: chunk.x.div_euclid(REGION_SIZE),
+            z: chunk.z.div_euclid(REGION_SIZE),
+        };
+        let slot = chunk.z.rem_euclid(REGION_SIZE) * REGION_SIZE + chunk.x.rem_euclid(REGION_SIZE);
+        (region, slot as usize)
+    }
+
+    fn file_name(&self) -> String {
+        format!("r.{}.{}.region", self.x, self.z)
+    }
+}
+
+struct Region {
+    table: Vec<TableEntry>,
+    /// Zero until the file has been written with the current header.
+    file_len: u64,
+}
+
+impl Region {
+    /// Bytes of chunk data still referenced by the table.
+    fn live_bytes(&self) -> u64 {
+        self.table.iter().map(|entry| entry.length as u64).sum()
+    }
+}
+
+/// Generated chunks saved to disk, grouped into region files of
+/// `REGION_SIZE` x `REGION_SIZE` chunks.
+///
+/// A region file is a `RegionHeader`, a table with one `TableEntry` per
+/// chunk, then zlib compressed chunk data. Saving a chunk appends its data
+/// and repoints the table; the file is compacted once more than half of it
+/// is stale.
+pub struct RegionStore {
+    dir: PathBuf,
+    header: RegionHeader,
+    regions: HashMap<RegionCoord, Region>,
+}
+
+impl RegionStore {
+    pub fn open(
+        dir: impl Into<PathBuf>,
+        dimensions: &ChunkDimensions,
+        lod_count: u32,
+        seed: u32,
+        erosion: Option<ErosionOptions>,
+    ) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create save directory {}", dir.display()))?;
+
+        Ok(Self {
+            dir,
+            header: RegionHeader {
+                magic: MAGIC,
+                version: VERSION,
+                seed: seed as u64,
+                resolution: dimensions.resolution.into(),
+                extent: dimensions.extent.into(),
+                lod_count,
+                region_size: REGION_SIZE as u32,
+                erosion: erosion.unwrap_or_else(bytemuck::Zeroable::zeroed),
+            },
+            regions: HashMap::new(),
+        })
+    }
+
+    /// Reads a saved chunk, if there is one.
+    pub fn load(&mut self, coord: ChunkCoord) -> Result<Option<RawChunkData>> {
+        let (region, slot) = RegionCoord::of(coord);
+        let entry = self.region(region)?.table[slot];
+        if entry.length == 0 {
+            return Ok(None);
+        }
+
+        let path = self.path(region);
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::Start(entry.offset as u64))?;
+        let mut compressed = vec![0; entry.length as usize];
+        file.read_exact(&mut compressed)?;
+
+        decode_chunk(&compressed, self.header.lod_count)
+            .with_context(|| format!("chunk {:?} in {} is corrupt", coord, path.display()))
+            .map(Some)
+    }
+
+    /// Writes a chunk, replacing any earlier save of it.
+    pub fn save(&mut self, coord: ChunkCoord, chunk: &RawChunkData) -> Result<()> {
+        let data = encode_chunk(chunk)?;
+        let (region, slot) = RegionCoord::of(coord);
+        let path = self.path(region);
+        let header = self.header;
+        let region_data = self.region(region)?;
+        if region_data.file_len == 0 {
+            write_region(&path, &header, &region_data.table, &[])?;
+            region_data.file_len = DATA_START;
+        }
+
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        let offset = file.seek(SeekFrom::Start(region_data.file_len))?;
+        file.write_all(&data)?;
+
+        let entry = TableEntry {
+            offset: u32::try_from(offset).context("region file too large")?,
+            length: data.len() as u32,
+        };
+        file.seek(SeekFrom::Start(TABLE_START + (slot * size_of::<TableEntry>()) as u64))?;
+        file.write_all(bytemuck::bytes_of(&entry))?;
+
+        region_data.table[slot] = entry;
+        region_data.file_len = offset + data.len() as u64;
+
+        let stale = region_data.file_len - DATA_START - region_data.live_bytes();
+        if stale > region_data.live_bytes() {
+            self.compact(region)?;
+        }
+        Ok(())
+    }
+
+    fn path(&self, region: RegionCoord) -> PathBuf {
+        self.dir.join(region.file_name())
+    }
+
+    /// Table of the region, read from disk the first time. Missing or
+    /// outdated region files start out empty and are replaced on the first
+    /// save.
+    fn region(&mut self, region: RegionCoord) -> Result<&mut Region> {
+        if !self.regions.contains_key(&region) {
+            let data = self.read_region(&self.path(region))?.unwrap_or_else(|| Region {
+                table: vec![TableEntry::default(); TABLE_LEN],
+                file_len: 0,
+            });
+            self.regions.insert(region, data);
+        }
+        Ok(self.regions.get_mut(&region).unwrap())
+    }
+
+    fn read_region(&self, path: &Path) -> Result<Option<Region>> {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let file_len = file.metadata()?.len();
+        if file_len < DATA_START {
+            log::warn!("{} is truncated, regenerating it", path.display());
+            return Ok(None);
+        }
+
+        let mut header = [0; size_of::<RegionHeader>()];
+        file.read_exact(&mut header)?;
+        let header: RegionHeader = bytemuck::pod_read_unaligned(&header);
+        if header != self.header {
+            log::info!("{} was saved with other world settings, regenerating it", path.display());
+            return Ok(None);
+        }
+
+        let mut table = vec![0; TABLE_LEN * size_of::<TableEntry>()];
+        file.read_exact(&mut table)?;
+        let table: Vec<TableEntry> = bytemuck::pod_collect_to_vec(&table);
+        if table
+            .iter()
+            .any(|entry| entry.offset as u64 + entry.length as u64 > file_len)
+        {
+            log::warn!("{} has a corrupt table, regenerating it", path.display());
+            return Ok(None);
+        }
+
+        Ok(Some(Region { table, file_len }))
+    }
+
+    /// Rewrites the region with only the data its table still points at.
+    fn compact(&mut self, region: RegionCoord) -> Result<()> {
+        let path = self.path(region);
+        let region_data = self.regions.get_mut(&region).unwrap();
+        let mut file = File::open(&path)?;
+
+        let mut table = vec![TableEntry::default(); TABLE_LEN];
+        let mut data = Vec::new();
+        for (slot, entry) in region_data.table.iter().enumerate() {
+            if entry.length == 0 {
+                continue;
+            }
+            let mut chunk = vec![0; entry.length as usize];
+            file.seek(SeekFrom::Start(entry.offset as u64))?;
+            file.read_exact(&mut chunk)?;
+            table[slot] = TableEntry {
+                offset: (DATA_START + data.len() as u64) as u32,
+                length: entry.length,
+            };
+            data.extend_from_slice(&chunk);
+        }
+        drop(file);
+
+        // write next to the old file first so a crash never loses both
+        let temp_path = path.with_extension("region.tmp");
+        write_region(&temp_path, &self.header, &table, &data)?;
+        fs::rename(&temp_path, &path)?;
+
+        region_data.table = table;
+        region_data.file_len = DATA_START + data.len() as u64;
+        Ok(())
+    }
+}
+
+fn write_region(path: &Path, header: &RegionHeader, table: &[TableEntry], data: &[u8]) -> Result<()> {
+    let mut file = File::create(path)
+        .with_context(|| format!("failed to create {}", path.display()))?;
+    file.write_all(bytemuck::bytes_of(header))?;
+    file.write_all(bytemuck::cast_slice(table))?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+/// Every level of detail as vertex length, index length, vertex data,
+/// index data, all compressed together.
+fn encode_chunk(chunk: &RawChunkData) -> Result<Vec<u8>> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::fast());
+    for lod in &chunk.lods {
+        encoder.write_all(&(lod.vertex_data.len() as u32).to_le_bytes())?;
+        encoder.write_all(&(lod.index_data.len() as u32).to_le_bytes())?;
+        encoder.write_all(&lod.vertex_data)?;
+        encoder.write_all(&lod.index_data)?;
+    }
+    Ok(encoder.finish()?)
+}
+
+fn decode_chunk(compressed: &[u8], lod_count: u32) -> Result<RawChunkData> {
+    let mut data = Vec::new();
+    ZlibDecoder::new(compressed).read_to_end(&mut data)?;
+
+    let mut rest = data.as_slice();
+    let mut lods = Vec::new();
+    for _ in 0..lod_count {
+        let vertex_len = read_len(&mut rest)?;
+        let index_len = read_len(&mut rest)?;
+        lods.push(RawBufferData {
+            vertex_data: take(&mut rest, vertex_len)?.to_vec(),
+            index_data: take(&mut rest, index_len)?.to_vec(),
+        });
+    }
+    Ok(RawChunkData::new(lods))
+}
+
+fn take<'a>(rest: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if rest.len() < len {
+        bail!("chunk data ends early");
+    }
+    let (taken, remaining) = rest.split_at(len);
+    *rest = remaining;
+    Ok(taken)
+}
+
+fn read_len(rest: &mut &[u8]) -> Result<usize> {
+    let bytes = take(rest, size_of::<u32>())?;
+    Ok(u32::from_le_bytes(bytes.try_into().unwrap()) as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Vector2, Vector3};
+
+    use super::*;
+    use crate::world::mesh::build_chunk;
+    use crate::world::noise::{Biome, TerrainVertex};
+
+    const LOD_COUNT: u32 = 2;
+
+    /// An empty directory of its own for each test.
+    fn save_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust_game-region-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn dimensions() -> ChunkDimensions {
+        ChunkDimensions::new((8, 8).into(), (8.0, 8.0).into())
+    }
+
+    fn open(dir: &Path, seed: u32) -> RegionStore {
+        RegionStore::open(dir, &dimensions(), LOD_COUNT, seed, None).unwrap()
+    }
+
+    /// A chunk whose heights depend on `height`, so saves can be told apart.
+    fn chunk(coord: ChunkCoord, height: f32) -> RawChunkData {
+        build_chunk(coord, &dimensions(), LOD_COUNT, |p: Vector2<f32>| TerrainVertex {
+            position: Vector3::new(p.x, height + p.x * 0.1, p.y),
+            normal: Vector3::unit_y(),
+            biome: Biome::Plains,
+        })
+    }
+
+    fn assert_same(loaded: Option<RawChunkData>, saved: &RawChunkData) {
+        let loaded = loaded.expect("chunk wasn't saved");
+        assert_eq!(loaded.lods.len(), saved.lods.len());
+        for (loaded, saved) in loaded.lods.iter().zip(&saved.lods) {
+            assert_eq!(loaded.vertex_data, saved.vertex_data);
+            assert_eq!(loaded.index_data, saved.index_data);
+        }
+        assert_eq!(loaded.height_range, saved.height_range);
+    }
+
+    #[test]
+    fn loads_what_was_saved() {
+        let dir = save_dir("round-trip");
+        let coords = [ChunkCoord::new(0, 0), ChunkCoord::new(3, 5), ChunkCoord::new(-1, -20)];
+        let mut store = open(&dir, 7);
+        for (i, coord) in coords.iter().enumerate() {
+            store.save(*coord, &chunk(*coord, i as f32)).unwrap();
+        }
+
+        // read back from the files by a new store
+        let mut store = open(&dir, 7);
+        for (i, coord) in coords.iter().enumerate() {
+            assert_same(store.load(*coord).unwrap(), &chunk(*coord, i as f32));
+        }
+        assert!(store.load(ChunkCoord::new(1, 0)).unwrap().is_none());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compacts_chunks_saved_over() {
+        let dir = save_dir("compact");
+        let (a, b) = (ChunkCoord::new(0, 0), ChunkCoord::new(1, 0));
+        let mut store = open(&dir, 7);
+        store.save(b, &chunk(b, -4.0)).unwrap();
+        for i in 0..10 {
+            store.save(a, &chunk(a, i as f32)).unwrap();
+        }
+
+        let path = dir.join(RegionCoord::of(a).0.file_name());
+        let live = store.regions[&RegionCoord::of(a).0].live_bytes();
+        assert!(fs::metadata(&path).unwrap().len() <= DATA_START + 2 * live);
+        let mut store = open(&dir, 7);
+        assert_same(store.load(a).unwrap(), &chunk(a, 9.0));
+        assert_same(store.load(b).unwrap(), &chunk(b, -4.0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn ignores_regions_of_other_settings() {
+        let dir = save_dir("settings");
+        let coord = ChunkCoord::new(2, 2);
+        open(&dir, 7).save(coord, &chunk(coord, 1.0)).unwrap();
+
+        assert!(open(&dir, 8).load(coord).unwrap().is_none());
+        let mut other_lods = RegionStore::open(&dir, &dimensions(), LOD_COUNT + 1, 7, None).unwrap();
+        assert!(other_lods.load(coord).unwrap().is_none());
+
+        // an older format, with the header otherwise unchanged
+        let path = dir.join(RegionCoord::of(coord).0.file_name());
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(MAGIC.len() as u64)).unwrap();
+        file.write_all(&(VERSION - 1).to_ne_bytes()).unwrap();
+        drop(file);
+        assert!(open(&dir, 7).load(coord).unwrap().is_none());
+
+        // and saving again replaces it
+        let mut store = open(&dir, 7);
+        store.save(coord, &chunk(coord, 2.0)).unwrap();
+        assert_same(open(&dir, 7).load(coord).unwrap(), &chunk(coord, 2.0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn survives_damaged_files() {
+        let dir = save_dir("damaged");
+        let coord = ChunkCoord::new(0, 0);
+        let path = dir.join(RegionCoord::of(coord).0.file_name());
+        let save = |height| open(&dir, 7).save(coord, &chunk(coord, height)).unwrap();
+
+        // cut off inside the table
+        save(1.0);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(DATA_START - 1).unwrap();
+        drop(file);
+        assert!(open(&dir, 7).load(coord).unwrap().is_none());
+
+        // a table pointing past the end of the file
+        fs::remove_file(&path).unwrap();
+        save(1.0);
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(fs::metadata(&path).unwrap().len() - 1).unwrap();
+        drop(file);
+        assert!(open(&dir, 7).load(coord).unwrap().is_none());
+
+        // chunk data that doesn't decompress
+        fs::remove_file(&path).unwrap();
+        save(1.0);
+        let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.seek(SeekFrom::Start(DATA_START)).unwrap();
+        file.write_all(&[0xff; 8]).unwrap();
+        drop(file);
+        assert!(open(&dir, 7).load(coord).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
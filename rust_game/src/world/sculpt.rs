@@ -0,0 +1,350 @@
+use std::collections::{HashMap, HashSet};
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::lib::pipelines::load_chunks::RawChunkData;
+
+use super::mesh::build_chunk;
+use super::noise::TerrainVertex;
+use super::surface::GridVertex;
+use super::{ChunkCoord, Ray, World};
+
+/// How far ahead of the camera the brush reaches, in world units.
+pub const BRUSH_REACH: f32 = 200.0;
+
+pub const MIN_BRUSH_RADIUS: f32 = 1.0;
+pub const MAX_BRUSH_RADIUS: f32 = 50.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BrushMode {
+    Raise,
+    Lower,
+    /// Pulls heights towards the height of the point the brush is aimed at.
+    Flatten,
+    /// Pulls heights towards the average of their neighbours.
+    Smooth,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Brush {
+    pub mode: BrushMode,
+    /// World units from the centre to where the brush stops having an effect.
+    pub radius: f32,
+    /// Height added or removed per second at the centre of the brush. Flatten
+    /// and smooth close this fraction of the gap to their target per second.
+    pub strength: f32,
+}
+
+impl Default for Brush {
+    fn default() -> Self {
+        Self {
+            mode: BrushMode::Raise,
+            radius: 6.0,
+            strength: 4.0,
+        }
+    }
+}
+
+impl Brush {
+    pub fn set_radius(&mut self, radius: f32) {
+        self.radius = radius.clamp(MIN_BRUSH_RADIUS, MAX_BRUSH_RADIUS);
+    }
+
+    /// Share of the full effect at `distance` from the centre, easing from
+    /// one in the middle to zero at the radius.
+    fn falloff(&self, distance: f32) -> f32 {
+        let t = (1.0 - (distance / self.radius).powi(2)).max(0.0);
+        t * t
+    }
+}
+
+/// Grid vertices changed by one stroke of the brush, before and after.
+/// Besides the vertices it moved, a stroke changes the normals around them.
+#[derive(Default)]
+struct Edit {
+    vertices: HashMap<GridVertex, (TerrainVertex, TerrainVertex)>,
+}
+
+/// Applies the brush to the loaded terrain and keeps the undo history.
+///
+/// Only the most detailed level of a chunk is edited directly. The skirts
+/// and the lower levels of detail of every touched chunk are rebuilt from it.
+#[derive(Default)]
+pub struct Sculptor {
+    pub brush: Brush,
+    /// Stroke in progress while the brush is held down.
+    stroke: Option<Edit>,
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl Sculptor {
+    pub fn is_sculpting(&self) -> bool {
+        self.stroke.is_some()
+    }
+
+    pub fn begin_stroke(&mut self) {
+        self.end_stroke();
+        self.stroke = Some(Edit::default());
+    }
+
+    /// Finishes the stroke in progress, making it the next edit to undo.
+    pub fn end_stroke(&mut self) {
+        if let Some(stroke) = self.stroke.take() {
+            if !stroke.vertices.is_empty() {
+                self.undo.push(stroke);
+                self.redo.clear();
+            }
+        }
+    }
+
+    /// Applies the brush for `dt` seconds where the ray from `origin` along
+    /// `direction` meets the terrain. Does nothing outside a stroke, or on
+    /// voxel terrain. Chunks the ray and the brush reach are read back from
+    /// the GPU first if need be, and the brush waits for them to land.
+    pub fn sculpt(
+        &mut self,
+        world: &mut World,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        origin: Vector3<f32>,
+        direction: Vector3<f32>,
+        dt: f32,
+    ) {
+        let Some(stroke) = self.stroke.as_mut().filter(|_| world.is_heightfield()) else {
+            return;
+        };
+        let ray = Ray::new(origin, direction, BRUSH_REACH);
+        let crossed: Vec<ChunkCoord> = world.ray_chunks(&ray).into_iter().map(|(coord, _)| coord).collect();
+        if !world.read_back_chunks(device, queue, crossed) {
+            return;
+        }
+        let Some(hit) = world.raycast(&ray).map(|hit| hit.position) else {
+            return;
+        };
+        if !world.read_back_chunks(device, queue, world.brush_chunks(&self.brush, hit)) {
+            return;
+        }
+
+        let heights = world.brush_heights(&self.brush, hit, dt);
+        for (vertex, before, after) in world.set_grid_heights(queue, heights) {
+            // keep the vertex from before the stroke first touched it
+            stroke.vertices.entry(vertex).or_insert((before, after)).1 = after;
+        }
+    }
+
+    /// Reverts the last stroke. Returns false if there was nothing to undo.
+    /// Chunks it touches are read back from the GPU first if need be, and
+    /// until they land the stroke stays where it is.
+    pub fn undo(&mut self, world: &mut World, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.end_stroke();
+        let Some(edit) = self.undo.pop() else {
+            return false;
+        };
+        if !world.read_back_chunks(device, queue, world.edit_chunks(&edit)) {
+            log::info!("Waiting for the terrain to load before undoing");
+            self.undo.push(edit);
+            return true;
+        }
+        world.set_grid_vertices(queue, edit.vertices.iter().map(|(vertex, (before, _))| (*vertex, *before)));
+        self.redo.push(edit);
+        true
+    }
+
+    /// Applies the last undone stroke again, like `undo`. Returns false if
+    /// there was nothing to redo.
+    pub fn redo(&mut self, world: &mut World, device: &wgpu::Device, queue: &wgpu::Queue) -> bool {
+        self.end_stroke();
+        let Some(edit) = self.redo.pop() else {
+            return false;
+        };
+        if !world.read_back_chunks(device, queue, world.edit_chunks(&edit)) {
+            log::info!("Waiting for the terrain to load before redoing");
+            self.redo.push(edit);
+            return true;
+        }
+        world.set_grid_vertices(queue, edit.vertices.iter().map(|(vertex, (_, after))| (*vertex, *after)));
+        self.undo.push(edit);
+        true
+    }
+}
+
+impl World {
+    /// Chunks sculpted since the last call, to be saved.
+    pub fn take_edited_chunks(&mut self) -> Vec<(ChunkCoord, RawChunkData)> {
+        self.edited_chunks
+            .drain()
+            .filter_map(|coord| Some((coord, self.raw_chunk_data.get(&coord)?.clone())))
+            .collect()
+    }
+
+    fn grid_point(&self, (x, z): GridVertex) -> Vector2<f32> {
+        let spacing = self.chunk_dimensions.spacing();
+        Vector2::new(x as f32 * spacing.x, z as f32 * spacing.y)
+    }
+
+    /// Normal from the slope between the neighbouring grid vertices, using
+    /// the vertex itself for any neighbour that isn't loaded.
+    fn grid_normal(&self, (x, z): GridVertex) -> Option<Vector3<f32>> {
+        let spacing = self.chunk_dimensions.spacing();
+        let height = self.grid_height((x, z))?;
+        let h = |vertex| self.grid_height(vertex).unwrap_or(height);
+        let dx = (h((x + 1, z)) - h((x - 1, z))) / (2.0 * spacing.x);
+        let dz = (h((x, z + 1)) - h((x, z - 1))) / (2.0 * spacing.y);
+        Some(Vector3::new(-dx, 1.0, -dz).normalize())
+    }
+
+    /// Chunks a brush centred on `hit` may change, reaching one grid cell
+    /// past its radius for the slopes around the vertices it moves.
+    fn brush_chunks(&self, brush: &Brush, hit: Vector3<f32>) -> Vec<ChunkCoord> {
+        let spacing = self.chunk_dimensions.spacing();
+        let reach = Vector2::new(brush.radius + spacing.x, brush.radius + spacing.y);
+        let (low, high) = (hit.xz() - reach, hit.xz() + reach);
+        let low = ChunkCoord::from_world(low.x, low.y, &self.chunk_dimensions);
+        let high = ChunkCoord::from_world(high.x, high.y, &self.chunk_dimensions);
+        (low.z..=high.z)
+            .flat_map(|z| (low.x..=high.x).map(move |x| ChunkCoord::new(x, z)))
+            .collect()
+    }
+
+    /// Chunks holding any vertex of an edit.
+    fn edit_chunks(&self, edit: &Edit) -> Vec<ChunkCoord> {
+        edit.vertices
+            .keys()
+            .flat_map(|vertex| self.grid_chunks(*vertex))
+            .map(|(coord, _)| coord)
+            .collect()
+    }
+
+    /// New heights for every loaded grid vertex under a brush centred on
+    /// `hit`.
+    fn brush_heights(&self, brush: &Brush, hit: Vector3<f32>, dt: f32) -> Vec<(GridVertex, f32)> {
+        let spacing = self.chunk_dimensions.spacing();
+        let center = hit.xz();
+        let amount = brush.strength * dt;
+        let min_x = ((center.x - brush.radius) / spacing.x).ceil() as i32;
+        let max_x = ((center.x + brush.radius) / spacing.x).floor() as i32;
+        let min_z = ((center.y - brush.radius) / spacing.y).ceil() as i32;
+        let max_z = ((center.y + brush.radius) / spacing.y).floor() as i32;
+
+        let mut changes = Vec::new();
+        for z in min_z..=max_z {
+            for x in min_x..=max_x {
+                let distance = (self.grid_point((x, z)) - center).magnitude();
+                if distance >= brush.radius {
+                    continue;
+                }
+                let Some(height) = self.grid_height((x, z)) else {
+                    continue;
+                };
+
+                let effect = amount * brush.falloff(distance);
+                let after = match brush.mode {
+                    BrushMode::Raise => height + effect,
+                    BrushMode::Lower => height - effect,
+                    BrushMode::Flatten => height + (hit.y - height) * effect.min(1.0),
+                    BrushMode::Smooth => {
+                        let neighbours: Vec<f32> = [(x - 1, z), (x + 1, z), (x, z - 1), (x, z + 1)]
+                            .into_iter()
+                            .filter_map(|vertex| self.grid_height(vertex))
+                            .collect();
+                        if neighbours.is_empty() {
+                            continue;
+                        }
+                        let average = neighbours.iter().sum::<f32>() / neighbours.len() as f32;
+                        height + (average - height) * effect.min(1.0)
+                    }
+                };
+                changes.push(((x, z), after));
+            }
+        }
+        changes
+    }
+
+    /// Moves grid vertices to new heights and recomputes the normals around
+    /// them. Returns every vertex changed, before and after.
+    fn set_grid_heights(
+        &mut self,
+        queue: &wgpu::Queue,
+        heights: Vec<(GridVertex, f32)>,
+    ) -> Vec<(GridVertex, TerrainVertex, TerrainVertex)> {
+        // the slope changes on both sides of a moved vertex
+        let sloped: HashSet<GridVertex> = heights
+            .iter()
+            .flat_map(|&((x, z), _)| [(x, z), (x - 1, z), (x + 1, z), (x, z - 1), (x, z + 1)])
+            .collect();
+        let before: Vec<(GridVertex, TerrainVertex)> = sloped
+            .into_iter()
+            .filter_map(|vertex| Some((vertex, self.grid_vertex(vertex)?)))
+            .collect();
+
+        for (vertex, height) in heights {
+            for (coord, index) in self.grid_owners(vertex) {
+                let grid = &mut self.raw_chunk_data.get_mut(&coord).unwrap().lods[0];
+                let mut v = grid.vertex(index);
+                v.position.y = height;
+                grid.set_vertex(index, v);
+            }
+        }
+
+        let changes: Vec<_> = before
+            .into_iter()
+            .map(|(vertex, old)| {
+                let mut new = self.grid_vertex(vertex).unwrap();
+                new.normal = self.grid_normal(vertex).unwrap();
+                (vertex, old, new)
+            })
+            .collect();
+        self.set_grid_vertices(queue, changes.iter().map(|(vertex, _, new)| (*vertex, *new)));
+        changes
+    }
+
+    /// Writes grid vertices to every loaded chunk sharing them, then rebuilds
+    /// and uploads the touched chunks.
+    fn set_grid_vertices(
+        &mut self,
+        queue: &wgpu::Queue,
+        vertices: impl IntoIterator<Item = (GridVertex, TerrainVertex)>,
+    ) {
+        let mut touched = HashSet::new();
+        for (vertex, value) in vertices {
+            for (coord, index) in self.grid_owners(vertex) {
+                self.raw_chunk_data.get_mut(&coord).unwrap().lods[0].set_vertex(index, value);
+                touched.insert(coord);
+            }
+        }
+
+        for coord in touched {
+            self.rebuild_chunk(queue, coord);
+        }
+    }
+
+    /// Rebuilds the skirts and lower levels of detail of a chunk from its
+    /// most detailed grid, and uploads every level.
+    fn rebuild_chunk(&mut self, queue: &wgpu::Queue, coord: ChunkCoord) {
+        let dimensions = self.chunk_dimensions;
+        let Some(raw) = self.raw_chunk_data.get(&coord) else {
+            return;
+        };
+        let grid = &raw.lods[0];
+        let corner = coord.corner(&dimensions);
+        let spacing = dimensions.spacing();
+        let row = dimensions.resolution.x + 1;
+        // every lower level's vertices sit on grid vertices of the top level
+        let rebuilt = build_chunk(coord, &dimensions, raw.lods.len() as u32, |p| {
+            let x = ((p.x - corner.x) / spacing.x).round() as u32;
+            let z = ((p.y - corner.y) / spacing.y).round() as u32;
+            grid.vertex(z * row + x)
+        });
+
+        let bounds = self.chunk_bounds(coord, rebuilt.height_range);
+        if let Some(chunk) = self.chunks.get_mut(&coord) {
+            for (mesh, lod) in chunk.slot.lods.iter().zip(&rebuilt.lods) {
+                self.arena.write_vertices(queue, mesh, &lod.vertex_data);
+            }
+            chunk.bounds = bounds;
+        }
+        self.raw_chunk_data.insert(coord, rebuilt);
+        self.edited_chunks.insert(coord);
+    }
+}
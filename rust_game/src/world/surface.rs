@@ -0,0 +1,168 @@
+use cgmath::{InnerSpace, Vector3};
+
+use super::noise::TerrainVertex;
+use super::{ChunkCoord, World};
+
+/// Vertex of the most detailed grid, counted across the whole world so a
+/// vertex on a chunk border is the same vertex for every chunk sharing it.
+pub(super) type GridVertex = (i32, i32);
+
+impl World {
+    /// Height of the ground at a world position, on the triangles of the
+    /// most detailed level of the terrain. `None` where the chunk isn't in
+    /// `raw_chunk_data`, and on voxel terrain, which has no single height.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let mut height = 0.0;
+        for (vertex, weight) in self.surface_triangle(x, z)? {
+            height += self.grid_height(vertex)? * weight;
+        }
+        Some(height)
+    }
+
+    /// Normal of the ground at a world position, blended from the normals
+    /// of the triangle around it the way the terrain is shaded. `None` where
+    /// `height_at` is.
+    pub fn normal_at(&self, x: f32, z: f32) -> Option<Vector3<f32>> {
+        let mut normal = Vector3::new(0.0, 0.0, 0.0);
+        for (vertex, weight) in self.surface_triangle(x, z)? {
+            normal += self.grid_vertex(vertex)?.normal * weight;
+        }
+        Some(normal.normalize())
+    }
+
+    /// Corners of the triangle under a world position, each with its weight
+    /// at that position. Every grid cell is split from its lowest corner to
+    /// its highest, as `gen_terrain_compute` winds it.
+    fn surface_triangle(&self, x: f32, z: f32) -> Option<[(GridVertex, f32); 3]> {
+        if !self.heightfield {
+            return None;
+        }
+        let spacing = self.chunk_dimensions.spacing();
+        let (x, z) = (x / spacing.x, z / spacing.y);
+        let (tx, tz) = (x - x.floor(), z - z.floor());
+        let (x, z) = (x.floor() as i32, z.floor() as i32);
+
+        Some(if tz >= tx {
+            [((x, z), 1.0 - tz), ((x, z + 1), tz - tx), ((x + 1, z + 1), tx)]
+        } else {
+            [((x, z), 1.0 - tx), ((x + 1, z), tx - tz), ((x + 1, z + 1), tz)]
+        })
+    }
+
+    /// Loaded chunks containing a grid vertex, with the vertex's index in
+    /// each. Vertices on borders belong to two chunks, on corners to four.
+    pub(super) fn grid_owners(&self, vertex: GridVertex) -> Vec<(ChunkCoord, u32)> {
+        let mut owners = self.grid_chunks(vertex);
+        owners.retain(|(coord, _)| self.raw_chunk_data.contains_key(coord));
+        owners
+    }
+
+    /// Every chunk containing a grid vertex, loaded or not, with the
+    /// vertex's index in each.
+    pub(super) fn grid_chunks(&self, (x, z): GridVertex) -> Vec<(ChunkCoord, u32)> {
+        let resolution = self.chunk_dimensions.resolution;
+        let (rx, rz) = (resolution.x as i32, resolution.y as i32);
+        // (chunk, position within it) along one axis
+        let along = |v: i32, r: i32| match v.rem_euclid(r) {
+            0 => vec![(v.div_euclid(r), 0), (v.div_euclid(r) - 1, r)],
+            local => vec![(v.div_euclid(r), local)],
+        };
+
+        let mut owners = Vec::new();
+        for (chunk_z, local_z) in along(z, rz) {
+            for (chunk_x, local_x) in along(x, rx) {
+                let coord = ChunkCoord::new(chunk_x, chunk_z);
+                owners.push((coord, (local_z * (rx + 1) + local_x) as u32));
+            }
+        }
+        owners
+    }
+
+    pub(super) fn grid_vertex(&self, vertex: GridVertex) -> Option<TerrainVertex> {
+        let (coord, index) = *self.grid_owners(vertex).first()?;
+        Some(self.raw_chunk_data[&coord].lods[0].vertex(index))
+    }
+
+    pub(super) fn grid_height(&self, vertex: GridVertex) -> Option<f32> {
+        Some(self.grid_vertex(vertex)?.position.y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector2;
+
+    use super::*;
+    use crate::lib::options::{Options, TerrainSource};
+    use crate::testing::{gpu_test, loaded_world};
+    use crate::world::noise::{terrain_vertex, Biome};
+
+    gpu_test! {
+        fn follows_a_slope_across_chunks(device, queue) {
+            let options = Options::default();
+            let slope = |p: Vector2<f32>| TerrainVertex {
+                position: Vector3::new(p.x, 0.5 * p.x - 0.25 * p.y + 3.0, p.y),
+                normal: Vector3::new(-0.5, 1.0, 0.25).normalize(),
+                biome: Biome::Plains,
+            };
+            let world = loaded_world(&device, &queue, &options, slope);
+
+            // chunks are 32 units wide, so these cross borders and corners
+            for (x, z) in [(0.0, 0.0), (31.9, 32.0), (-32.0, 5.3), (40.7, -17.2), (-0.01, 63.9)] {
+                let height = world.height_at(x, z).unwrap();
+                assert!((height - slope(Vector2::new(x, z)).position.y).abs() < 1e-4, "({}, {})", x, z);
+                let normal = world.normal_at(x, z).unwrap();
+                assert!((normal - slope(Vector2::new(x, z)).normal).magnitude() < 1e-4);
+            }
+            assert_eq!(world.height_at(-32.5, 0.0), None);
+            assert_eq!(world.normal_at(0.0, 64.5), None);
+        }
+    }
+
+    gpu_test! {
+        fn stops_walking_up_steep_ground(device, queue) {
+            let options = Options::default();
+            // climbing steeply along x, under the sea west of x = -10
+            let world = loaded_world(&device, &queue, &options, |p| TerrainVertex {
+                position: Vector3::new(p.x, 2.0 * p.x + 0.2 * p.y + 20.0, p.y),
+                normal: Vector3::new(-2.0, 1.0, -0.2).normalize(),
+                biome: Biome::Plains,
+            });
+
+            assert!(!world.can_walk((5.0, 5.0), (6.0, 5.0)));
+            assert!(world.can_walk((6.0, 5.0), (5.0, 5.0)));
+            // swimming goes anywhere, and so does walking off the loaded chunks
+            assert!(world.can_walk((-15.0, 5.0), (-14.0, 5.0)));
+            assert!(world.can_walk((5.0, 5.0), (500.0, 5.0)));
+        }
+    }
+
+    gpu_test! {
+        fn samples_the_drawn_triangles(device, queue) {
+            let options = Options::default();
+            let world = loaded_world(&device, &queue, &options, |p| terrain_vertex(p, options.seed));
+            let height = |x, z| terrain_vertex(Vector2::new(x, z), options.seed).position.y;
+
+            // grid vertices are exact, and every cell is split along the
+            // diagonal from its lowest corner to its highest
+            for (x, z) in [(3.0, 4.0), (-7.0, 32.0), (32.0, -1.0)] {
+                assert_eq!(world.height_at(x, z), Some(height(x, z)));
+                let middle = world.height_at(x + 0.5, z + 0.5).unwrap();
+                assert!((middle - (height(x, z) + height(x + 1.0, z + 1.0)) * 0.5).abs() < 1e-4);
+                let on_edge = world.height_at(x + 0.25, z).unwrap();
+                assert!((on_edge - (height(x, z) * 0.75 + height(x + 1.0, z) * 0.25)).abs() < 1e-4);
+            }
+        }
+    }
+
+    gpu_test! {
+        fn has_no_height_on_voxel_terrain(device, queue) {
+            let options = Options {
+                terrain: TerrainSource::Voxel,
+                ..Default::default()
+            };
+            let world = loaded_world(&device, &queue, &options, |p| terrain_vertex(p, 0));
+            assert_eq!(world.height_at(1.0, 1.0), None);
+        }
+    }
+}
@@ -237,25 +237,43 @@ struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) normal: vec3<f32>,
     @location(1) world_pos: vec3<f32>,
+    // the biome of the triangle's first corner, coloured whole
+    @location(2) @interpolate(flat) biome: u32,
+}
+
+// Biome ids written by gen_terrain.wgsl
+const BIOME_MOUNTAINS: u32 = 1u;
+const BIOME_DESERT: u32 = 2u;
+const BIOME_SNOW: u32 = 3u;
+const BIOME_OCEAN: u32 = 4u;
+
+fn biome_color(biome: u32) -> vec3<f32> {
+    if (biome == BIOME_MOUNTAINS) { return vec3<f32>(0.45, 0.42, 0.4); }
+    if (biome == BIOME_DESERT) { return vec3<f32>(0.86, 0.76, 0.5); }
+    if (biome == BIOME_SNOW) { return vec3<f32>(0.92, 0.94, 0.97); }
+    if (biome == BIOME_OCEAN) { return vec3<f32>(0.55, 0.5, 0.4); }
+    // plains
+    return vec3<f32>(0.2, 0.7, 0.3);
 }
 
 @vertex
 fn vs_main(
     vertex: Vertex,
+    // stored in the padding after the position
+    @location(2) biome: u32,
 ) -> VertexOutput {
     let clip_position = camera.view_proj * vec4<f32>(vertex.position, 1.);
     let normal = vertex.normal;
-    return VertexOutput(clip_position, normal, vertex.position);
+    return VertexOutput(clip_position, normal, vertex.position, biome);
 }
 
+struct Fog {
+    color: vec4<f32>,
+    start: f32,
+    end: f32,
+}
 @group(2) @binding(0)
-var t_diffuse: texture_2d<f32>;
-@group(2) @binding(1)
-var s_diffuse: sampler;
-@group(2) @binding(2)
-var t_normal: texture_2d<f32>;
-@group(2) @binding(3)
-var s_normal: sampler;
+var<uniform> fog: Fog;
 
 fn color23(p: vec2<f32>) -> vec3<f32> {
     return vec3<f32>(
@@ -267,8 +285,9 @@ fn color23(p: vec2<f32>) -> vec3<f32> {
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    var color = smoothstep(vec3<f32>(0.0), vec3<f32>(0.0), fract(in.world_pos));
-    color = mix(vec3<f32>(0.2, 0.7, 0.4), vec3<f32>(0.2, 0.7, 0.2), vec3<f32>(color.x * color.y * color.z));
+    // steep slopes are bare rock whatever the biome
+    let steepness = 1.0 - smoothstep(0.55, 0.75, normalize(in.normal).y);
+    let color = mix(biome_color(in.biome), vec3<f32>(0.4, 0.38, 0.36), steepness);
 
     let ambient_strength = 0.1;
     let ambient_color = light.color * ambient_strength;
@@ -285,5 +304,8 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     let result = (ambient_color + diffuse_color + specular_color) * color;
 
-    return vec4<f32>(result, 1.0);
+    let distance = length(camera.view_pos.xz - in.world_pos.xz);
+    let fog_amount = smoothstep(fog.start, fog.end, distance);
+
+    return vec4<f32>(mix(result, fog.color.rgb, fog_amount), 1.0);
 }
\ No newline at end of file
@@ -0,0 +1,183 @@
+use wgpu::util::DeviceExt;
+
+use crate::lib::create_render_pipeline;
+use crate::lib::frustum::Frustum;
+use crate::lib::pipelines::load_chunks::Chunk;
+
+use super::{ChunkDimensions, World};
+
+/// Height of the camera above the ground when walking.
+const EYE_HEIGHT: f32 = 3.0;
+/// Closest the camera gets to the bottom when swimming.
+const SWIM_CLEARANCE: f32 = 1.0;
+/// Height of the camera above the surface when floating.
+const FLOAT_HEIGHT: f32 = 0.5;
+/// Speed the camera drifts back up to the surface under water.
+const BUOYANCY: f32 = 2.0;
+/// Steepest ground the camera walks up, as the up component of its normal.
+const MAX_WALK_SLOPE: f32 = 0.6;
+
+impl World {
+    pub fn sea_level(&self) -> f32 {
+        self.sea_level
+    }
+
+    /// Whether the camera can walk from `(x, z)` to `(to_x, to_z)`. Ground
+    /// too steep stops it going uphill, though not downhill, and not when
+    /// swimming. Free to go where the ground isn't known.
+    pub fn can_walk(&self, (x, z): (f32, f32), (to_x, to_z): (f32, f32)) -> bool {
+        let (Some(from), Some(to), Some(normal)) =
+            (self.height_at(x, z), self.height_at(to_x, to_z), self.normal_at(to_x, to_z))
+        else {
+            return true;
+        };
+        let swimming = to + EYE_HEIGHT < self.sea_level + FLOAT_HEIGHT;
+        swimming || to <= from || normal.y >= MAX_WALK_SLOPE
+    }
+
+    /// Camera height after a frame spent `dt` seconds at height `y` over
+    /// `ground`. On dry land the camera walks along the ground. Where the
+    /// ground is too deep to stand in it swims instead: free to dive down to
+    /// the bottom, and floating back up to the surface otherwise.
+    pub fn camera_height(&self, ground: f32, y: f32, dt: f32) -> f32 {
+        let standing = ground + EYE_HEIGHT;
+        let surface = self.sea_level + FLOAT_HEIGHT;
+        if standing >= surface {
+            return standing;
+        }
+        (y + BUOYANCY * dt).clamp(ground + SWIM_CLEARANCE, surface)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct WaterUniform {
+    sea_level: f32,
+    /// Seconds since the start, moving the waves.
+    time: f32,
+    _padding: [u32; 2],
+}
+
+/// Draws the water over every chunk dipping below the sea. It has no
+/// meshes of its own: the grid of each chunk's terrain in the `ChunkArena`
+/// is drawn again, flattened to sea level, so it is never read back to find
+/// the shoreline. They are transparent, so this has to come after all the
+/// opaque geometry in a render pass.
+pub struct WaterPipeline {
+    render_pipeline: wgpu::RenderPipeline,
+    uniform: WaterUniform,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl WaterPipeline {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_layout: &wgpu::BindGroupLayout,
+        light_layout: &wgpu::BindGroupLayout,
+        fog_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_format: Option<wgpu::TextureFormat>,
+        sea_level: f32,
+    ) -> Self {
+        let uniform = WaterUniform {
+            sea_level,
+            time: 0.0,
+            _padding: [0; 2],
+        };
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("WaterPipeline: Uniform"),
+            contents: bytemuck::bytes_of(&uniform),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("WaterPipeline::Layout"),
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("WaterPipeline: BindGroup"),
+            layout: &layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+        });
+
+        let shader = device.create_shader_module(wgpu::include_wgsl!("water.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("WaterPipeline::Render::PipelineLayout"),
+            bind_group_layouts: &[camera_layout, light_layout, fog_layout, &layout],
+            push_constant_ranges: &[],
+        });
+        let render_pipeline = create_render_pipeline(
+            device,
+            &pipeline_layout,
+            color_format,
+            depth_format,
+            // just the positions of the terrain vertices in the arena
+            &[wgpu::VertexBufferLayout {
+                array_stride: ChunkDimensions::VERTEX_SIZE as wgpu::BufferAddress,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &wgpu::vertex_attr_array![0 => Float32x3],
+            }],
+            &shader,
+            wgpu::BlendState::ALPHA_BLENDING,
+        );
+
+        Self {
+            render_pipeline,
+            uniform,
+            uniform_buffer,
+            bind_group,
+        }
+    }
+
+    /// Moves the waves on by `dt` seconds.
+    pub fn animate(&mut self, queue: &wgpu::Queue, dt: f32) {
+        self.uniform.time += dt;
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::bytes_of(&self.uniform));
+    }
+
+    pub fn render<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        world: &'a World,
+        frustum: &Frustum,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        fog_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, light_bind_group, &[]);
+        render_pass.set_bind_group(2, fog_bind_group, &[]);
+        render_pass.set_bind_group(3, &self.bind_group, &[]);
+        if !world.heightfield {
+            return;
+        }
+        render_pass.set_index_buffer(world.arena.index_buffer().slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_vertex_buffer(0, world.arena.vertex_buffer().slice(..));
+        // a chunk reaching below the sea at any level of detail may have
+        // water at the one drawn, where it doesn't the water is all clear
+        let wet = |chunk: &&Chunk| {
+            chunk.bounds.min.y < world.sea_level
+                && frustum.intersects(&chunk.bounds.including_height(world.sea_level))
+        };
+        for chunk in world.chunks.values().filter(wet) {
+            // the grid without its skirts, at the level of the terrain under it
+            let mesh = chunk.slot.lods[chunk.lod];
+            let num_indices = world.chunk_dimensions.lod(chunk.lod as u32).grid_indices();
+            let indices = mesh.first_index..mesh.first_index + num_indices;
+            render_pass.draw_indexed(indices, mesh.base_vertex as i32, 0..1);
+        }
+    }
+}
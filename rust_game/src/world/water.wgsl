@@ -0,0 +1,93 @@
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+struct Fog {
+    color: vec4<f32>,
+    start: f32,
+    end: f32,
+}
+@group(2) @binding(0)
+var<uniform> fog: Fog;
+
+struct Water {
+    sea_level: f32,
+    time: f32,
+}
+@group(3) @binding(0)
+var<uniform> water: Water;
+
+struct VertexInput {
+    // of the terrain, the water lies over it at sea level
+    @location(0) position: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) world_pos: vec3<f32>,
+    @location(1) depth: f32,
+}
+
+@vertex
+fn vs_main(vertex: VertexInput) -> VertexOutput {
+    let world_pos = vec3<f32>(vertex.position.x, water.sea_level, vertex.position.z);
+    // from the surface down to the terrain, negative where the terrain is dry
+    let depth = water.sea_level - vertex.position.y;
+    return VertexOutput(camera.view_proj * vec4<f32>(world_pos, 1.0), world_pos, depth);
+}
+
+// Slope of one sine wave travelling along `direction`, added to `slope`
+fn wave(slope: vec2<f32>, p: vec2<f32>, direction: vec2<f32>, wavelength: f32, amplitude: f32, speed: f32) -> vec2<f32> {
+    let k = 6.2831853 / wavelength;
+    let phase = dot(direction, p) * k + water.time * speed;
+    return slope + direction * (amplitude * k * cos(phase));
+}
+
+// Surface normal of a few waves running across each other
+fn wave_normal(p: vec2<f32>) -> vec3<f32> {
+    var slope = vec2<f32>(0.0);
+    slope = wave(slope, p, normalize(vec2<f32>(1.0, 0.3)), 9.0, 0.08, 1.3);
+    slope = wave(slope, p, normalize(vec2<f32>(-0.4, 1.0)), 5.7, 0.05, 1.9);
+    slope = wave(slope, p, normalize(vec2<f32>(0.8, -0.7)), 3.1, 0.025, 2.6);
+    return normalize(vec3<f32>(-slope.x, 1.0, -slope.y));
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let normal = wave_normal(in.world_pos.xz);
+    let view_dir = normalize(camera.view_pos.xyz - in.world_pos);
+    let light_dir = normalize(light.position - in.world_pos);
+    let half_dir = normalize(view_dir + light_dir);
+
+    // deeper water is darker and hides more of the bottom
+    let depth = max(in.depth, 0.0);
+    let murk = 1.0 - exp(-depth * 0.15);
+    let shallow_color = vec3<f32>(0.1, 0.5, 0.55);
+    let deep_color = vec3<f32>(0.02, 0.1, 0.22);
+    let body = mix(shallow_color, deep_color, murk);
+
+    let diffuse = 0.1 + max(dot(normal, light_dir), 0.0);
+    let specular = pow(max(dot(normal, half_dir), 0.0), 128.0) * light.color;
+
+    // Schlick's approximation, water reflects 2% of the sky head on
+    let fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, view_dir), 0.0), 5.0);
+    let color = mix(body * diffuse * light.color, fog.color.rgb, fresnel) + specular;
+    // glancing views see the reflection rather than through the water, and
+    // it fades out where it thins to nothing at the shore
+    let alpha = mix(mix(0.45, 0.9, murk), 1.0, fresnel) * smoothstep(0.0, 0.3, depth);
+
+    let distance = length(camera.view_pos.xz - in.world_pos.xz);
+    let fog_amount = smoothstep(fog.start, fog.end, distance);
+
+    return vec4<f32>(mix(color, fog.color.rgb, fog_amount), alpha);
+}